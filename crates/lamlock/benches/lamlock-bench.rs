@@ -94,7 +94,7 @@ fn initialization<T: Schedule<HashMap<String, String>>>() {
             let lock = &lock;
             scope.spawn(move || {
                 lock.schedule(|data| {
-                    if data.len() == 0 {
+                    if data.is_empty() {
                         for i in 0..2048 {
                             data.insert((1000000 + i).to_string(), i.to_string());
                         }
@@ -108,6 +108,26 @@ fn initialization<T: Schedule<HashMap<String, String>>>() {
     });
 }
 
+fn initialization_broadcast() {
+    let lock = Lock::new(HashMap::new());
+    std::thread::scope(|scope| {
+        for _ in 0..256 {
+            let lock = &lock;
+            scope.spawn(move || {
+                lock.broadcast(|data: &mut HashMap<String, String>| {
+                    if data.is_empty() {
+                        for i in 0..2048 {
+                            data.insert((1000000 + i).to_string(), i.to_string());
+                        }
+                    }
+                    data.len()
+                })
+                .unwrap();
+            });
+        }
+    });
+}
+
 fn integer_add_bench_bad<T: Schedule<i32>>() {
     let lock = T::new(0);
     std::thread::scope(|scope| {
@@ -124,6 +144,20 @@ fn integer_add_bench_bad<T: Schedule<i32>>() {
     });
 }
 
+/// Run `f` with the current thread (and every thread it spawns) pinned to a single CPU,
+/// emulating an oversubscribed machine.
+fn on_single_core<F: FnOnce()>(f: F) {
+    let original = rustix::thread::sched_getaffinity(None).unwrap();
+    let cpu = (0..rustix::thread::CpuSet::MAX_CPU)
+        .find(|&cpu| original.is_set(cpu))
+        .unwrap();
+    let mut pinned = rustix::thread::CpuSet::new();
+    pinned.set(cpu);
+    rustix::thread::sched_setaffinity(None, &pinned).unwrap();
+    f();
+    rustix::thread::sched_setaffinity(None, &original).unwrap();
+}
+
 pub fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("integer add (lamlock)", |b| {
         b.iter(integer_add_bench::<Lock<i32>>)
@@ -157,12 +191,23 @@ pub fn criterion_benchmark(c: &mut Criterion) {
         b.iter(initialization::<Mutex<HashMap<String, String>>>)
     });
 
+    c.bench_function("initialization broadcast (lamlock)", |b| {
+        b.iter(initialization_broadcast)
+    });
+
     c.bench_function("integer add bad (lamlock)", |b| {
         b.iter(integer_add_bench_bad::<Lock<i32>>)
     });
     c.bench_function("integer add bad (mutex)", |b| {
         b.iter(integer_add_bench_bad::<Mutex<i32>>)
     });
+
+    c.bench_function("integer add single core (lamlock)", |b| {
+        b.iter(|| on_single_core(integer_add_bench::<Lock<i32>>))
+    });
+    c.bench_function("integer add single core (mutex)", |b| {
+        b.iter(|| on_single_core(integer_add_bench::<Mutex<i32>>))
+    });
 }
 
 criterion_group!(benches, criterion_benchmark);
@@ -0,0 +1,51 @@
+use crate::node::SPIN_LIMIT;
+
+/// Adaptive backoff for busy-waiting loops.
+///
+/// The first [`SPIN_LIMIT`] rounds only issue a spin hint. Afterwards, if the `yield` feature
+/// is enabled, every round gives up the timeslice via `sched_yield` so that the thread we are
+/// waiting for gets a chance to run on an oversubscribed system.
+pub struct Backoff {
+    rounds: usize,
+}
+
+impl Backoff {
+    pub const fn new() -> Self {
+        Self { rounds: 0 }
+    }
+
+    #[inline(always)]
+    pub fn snooze(&mut self) {
+        if self.rounds < SPIN_LIMIT {
+            self.rounds += 1;
+            core::hint::spin_loop();
+            return;
+        }
+
+        #[cfg(all(feature = "yield", not(miri)))]
+        rustix::thread::sched_yield();
+
+        #[cfg(not(all(feature = "yield", not(miri))))]
+        core::hint::spin_loop();
+    }
+
+    /// Whether the spinning phase is over and the caller should consider blocking instead.
+    #[cfg(feature = "std")]
+    pub fn is_completed(&self) -> bool {
+        self.rounds >= SPIN_LIMIT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_snooze() {
+        let mut backoff = Backoff::new();
+        for _ in 0..SPIN_LIMIT * 2 {
+            backoff.snooze();
+        }
+        assert_eq!(backoff.rounds, SPIN_LIMIT);
+    }
+}
@@ -1,14 +1,19 @@
 use core::{mem::ManuallyDrop, ptr::NonNull, sync::atomic::Ordering};
 
-use crate::{node::Node, rawlock::RawLock};
+use crate::{PoisonKind, backoff::Backoff, node::Node, rawlock::RawLock};
 
 pub struct LightWeightBomb<'a> {
     raw: &'a RawLock,
+    kind: PoisonKind,
 }
 
 impl<'a> LightWeightBomb<'a> {
     pub fn new(raw: &'a RawLock) -> Self {
-        Self { raw }
+        Self::with_kind(raw, PoisonKind::Panic)
+    }
+
+    pub fn with_kind(raw: &'a RawLock, kind: PoisonKind) -> Self {
+        Self { raw, kind }
     }
 
     pub fn get_raw(&self) -> &'a RawLock {
@@ -23,7 +28,7 @@ impl<'a> LightWeightBomb<'a> {
 impl<'a> Drop for LightWeightBomb<'a> {
     #[cold]
     fn drop(&mut self) {
-        self.raw.poison();
+        self.raw.poison(self.kind);
     }
 }
 
@@ -54,8 +59,9 @@ impl<'a> Drop for HeavyWeightBomb<'a> {
             // Otherwise, we know that the next will be updated since there are nodes waiting.
             // Unlike the combining path in the normal case, we continue to wake up further nodes.
             // This should end soon as the lock is poisoned. New nodes will not attach to the tail.
+            let mut backoff = Backoff::new();
             while unsafe { self.atom.as_ref().load_next(Ordering::Relaxed).is_none() } {
-                core::hint::spin_loop();
+                backoff.snooze();
             }
         }
     }
@@ -63,8 +69,11 @@ impl<'a> Drop for HeavyWeightBomb<'a> {
 
 impl<'a> HeavyWeightBomb<'a> {
     pub fn new(lock: &'a RawLock, atom: NonNull<Node>) -> Self {
+        Self::with_kind(lock, atom, PoisonKind::Panic)
+    }
+    pub fn with_kind(lock: &'a RawLock, atom: NonNull<Node>, kind: PoisonKind) -> Self {
         Self {
-            ignitor: ManuallyDrop::new(LightWeightBomb::new(lock)),
+            ignitor: ManuallyDrop::new(LightWeightBomb::with_kind(lock, kind)),
             atom,
         }
     }
@@ -98,7 +107,7 @@ mod tests {
         std::thread::scope(|s| {
             let raw = &raw;
             s.spawn(move || {
-                LightWeightBomb::new(&raw);
+                LightWeightBomb::new(raw);
             });
             while !raw.is_poisoned(core::sync::atomic::Ordering::Acquire) {
                 core::hint::spin_loop();
@@ -115,21 +124,18 @@ mod tests {
             let raw = &raw;
             let barrier = &barrier;
             for _ in 0..NUM_THREADS {
-                s.spawn({
-                    let raw = raw;
-                    move || {
-                        let node = Node::new(|_| {});
-                        let this = NonNull::from(&node);
-                        if let Some(prev) = raw.swap_tail(this) {
-                            unsafe {
-                                prev.as_ref().store_next(this);
-                            }
-                            barrier.wait();
-                            assert!(node.wait() == node::POISONED);
-                        } else {
-                            let _bomb = HeavyWeightBomb::new(raw, this);
-                            barrier.wait();
+                s.spawn(move || {
+                    let node = Node::new(|_| {});
+                    let this = NonNull::from(&node);
+                    if let Some(prev) = raw.swap_tail(this) {
+                        unsafe {
+                            prev.as_ref().store_next(this);
                         }
+                        barrier.wait();
+                        assert!(node.wait() == node::POISONED);
+                    } else {
+                        let _bomb = HeavyWeightBomb::new(raw, this);
+                        barrier.wait();
                     }
                 });
             }
@@ -0,0 +1,110 @@
+use core::{
+    any::TypeId,
+    cell::{Cell, UnsafeCell},
+    ptr::NonNull,
+};
+
+use crate::{
+    LockResult,
+    node::{Node, Share},
+    rawlock::RawLock,
+};
+
+/// A queue node carrying a closure, the data it runs on and a slot for its result.
+/// The node lives on the stack of the waiting thread.
+///
+/// Both the closure and the result are kept in `Option`s so that whatever is left in the node is
+/// dropped with it: a closure that never ran because the lock got poisoned is dropped on the waiting
+/// thread, and a result is only ever moved out once.
+#[repr(C)]
+pub struct CombinedNode<'a, T: ?Sized, F, R> {
+    node: Node,
+    closure: Cell<Option<F>>,
+    data: &'a UnsafeCell<T>,
+    result: Cell<Option<R>>,
+}
+
+impl<'a, T: ?Sized, F, R> CombinedNode<'a, T, F, R>
+where
+    F: FnOnce(&mut T) -> R,
+{
+    pub fn new(closure: F, data: &'a UnsafeCell<T>) -> Self {
+        Self::with_node(Node::new(Self::execute), closure, data)
+    }
+
+    fn with_node(node: Node, closure: F, data: &'a UnsafeCell<T>) -> Self {
+        Self {
+            node,
+            closure: Cell::new(Some(closure)),
+            data,
+            result: Cell::new(None),
+        }
+    }
+
+    unsafe fn execute(this: NonNull<Node>) {
+        let this = this.cast::<Self>();
+        let Some(closure) = (unsafe { this.as_ref().closure.take() }) else {
+            return;
+        };
+        let data = unsafe { &mut *this.as_ref().data.get() };
+        let result = (closure)(data);
+        unsafe { this.as_ref().result.set(Some(result)) };
+    }
+
+    /// Enqueue the node and wait until its closure has been executed.
+    pub fn attach(self, raw: &RawLock) -> LockResult<R> {
+        let this = NonNull::from(&self).cast();
+        Node::attach(this, raw)?;
+        // A node is only woken as done after its result has been stored.
+        Ok(self
+            .result
+            .into_inner()
+            .expect("combined node finished without a result"))
+    }
+}
+
+impl<'a, T: ?Sized, R> CombinedNode<'a, T, fn(&mut T) -> R, R>
+where
+    R: Clone + 'static,
+{
+    const SHARE: Share = Share {
+        key: Self::key,
+        share: Self::share,
+    };
+
+    /// Create a node whose result can be handed to queued successors running the same function.
+    pub fn shared(function: fn(&mut T) -> R, data: &'a UnsafeCell<T>) -> Self {
+        Self::with_node(
+            Node::with_share(Self::execute_shared, &Self::SHARE),
+            function,
+            data,
+        )
+    }
+
+    /// Same as [`CombinedNode::execute`], but the function stays in the node to identify it.
+    unsafe fn execute_shared(this: NonNull<Node>) {
+        let this = this.cast::<Self>();
+        let Some(function) = (unsafe { this.as_ref().closure.get() }) else {
+            return;
+        };
+        let data = unsafe { &mut *this.as_ref().data.get() };
+        let result = function(data);
+        unsafe { this.as_ref().result.set(Some(result)) };
+    }
+
+    /// Nodes share results only if they run the same function with the same result type.
+    unsafe fn key(this: NonNull<Node>) -> (TypeId, usize) {
+        let function = unsafe { this.cast::<Self>().as_ref().closure.get() };
+        (
+            TypeId::of::<R>(),
+            function.map_or(0, |function| function as usize),
+        )
+    }
+
+    unsafe fn share(src: NonNull<Node>, dst: NonNull<Node>) {
+        let src = src.cast::<Self>();
+        let dst = dst.cast::<Self>();
+        let result = unsafe { (*src.as_ref().result.as_ptr()).clone() };
+        unsafe { dst.as_ref().result.set(result) };
+    }
+}
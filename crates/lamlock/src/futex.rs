@@ -3,6 +3,9 @@ use core::{
     sync::atomic::{AtomicU32, Ordering},
 };
 
+/// A word threads can sleep on until it changes.
+///
+/// Without the `futex` feature (or under miri), waiting degrades to spinning on the word.
 #[repr(transparent)]
 pub struct Futex(AtomicU32);
 
@@ -23,7 +26,7 @@ impl Futex {
 
     #[inline(always)]
     pub fn wait(this: NonNull<Self>, value: u32) {
-        #[cfg(not(miri))]
+        #[cfg(all(feature = "futex", not(miri)))]
         while unsafe { this.as_ref().load(Ordering::Acquire) == value } {
             while let Err(rustix::io::Errno::INTR) = rustix::thread::futex::wait(
                 unsafe { &this.as_ref().0 },
@@ -35,7 +38,7 @@ impl Futex {
             }
         }
 
-        #[cfg(miri)]
+        #[cfg(any(not(feature = "futex"), miri))]
         while unsafe { this.as_ref().load(Ordering::Acquire) == value } {
             core::hint::spin_loop();
         }
@@ -43,7 +46,7 @@ impl Futex {
 
     #[inline(always)]
     pub fn notify(this: NonNull<Self>, new_val: u32, #[allow(unused)] old_val: u32) {
-        #[cfg(not(miri))]
+        #[cfg(all(feature = "futex", not(miri)))]
         if unsafe { this.as_ref().swap(new_val, Ordering::AcqRel) == old_val } {
             let _ = rustix::thread::futex::wake(
                 unsafe { &this.as_ref().0 },
@@ -52,7 +55,7 @@ impl Futex {
             );
         }
 
-        #[cfg(miri)]
+        #[cfg(any(not(feature = "futex"), miri))]
         unsafe {
             this.as_ref().store(new_val, Ordering::Release);
         }
@@ -0,0 +1,136 @@
+use core::{
+    ops::{Deref, DerefMut},
+    ptr::NonNull,
+};
+
+use std::boxed::Box;
+
+use crate::{
+    Lock, LockResult,
+    bomb::{HeavyWeightBomb, LightWeightBomb},
+    node::Node,
+};
+
+/// An RAII guard holding a [`Lock`], created by [`Lock::lock`].
+///
+/// The lock is released when the guard is dropped. If the guard is dropped during a panic,
+/// the lock is poisoned and all waiting threads are notified.
+pub struct Guard<'a, T: ?Sized> {
+    lock: &'a Lock<T>,
+    /// The holder node in the queue if the lock was contended, `None` if it was taken on the fast path.
+    node: Option<NonNull<Node>>,
+}
+
+unsafe impl<T: ?Sized + Sync> Sync for Guard<'_, T> {}
+
+impl<'a, T: ?Sized> Guard<'a, T> {
+    pub(crate) fn new(lock: &'a Lock<T>) -> LockResult<Self> {
+        let raw = &lock.raw;
+        raw.check_reentrancy();
+        let node = if lock.try_barge()? {
+            None
+        } else {
+            // The node must stay at a fixed address while the guard may be moved around.
+            let node = NonNull::from(Box::leak(Box::new(Node::holder())));
+            if let Err(err) = Node::acquire(node, raw) {
+                drop(unsafe { Box::from_raw(node.as_ptr()) });
+                return Err(err);
+            }
+            Some(node)
+        };
+        raw.enter_critical();
+        raw.record_batch(1);
+        Ok(Self { lock, node })
+    }
+}
+
+impl<T: ?Sized> Deref for Guard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for Guard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for Guard<'_, T> {
+    fn drop(&mut self) {
+        let raw = &self.lock.raw;
+        let panicking = std::thread::panicking();
+        match self.node {
+            None if panicking => drop(LightWeightBomb::new(raw)),
+            None => {
+                raw.exit_critical();
+                raw.release();
+            }
+            Some(node) => {
+                if panicking {
+                    drop(HeavyWeightBomb::new(raw, node));
+                } else {
+                    raw.exit_critical();
+                    Node::release(node, raw);
+                }
+                drop(unsafe { Box::from_raw(node.as_ptr()) });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Lock;
+
+    extern crate std;
+
+    #[test]
+    fn multi_thread_lock_and_run() {
+        let cnt = 100;
+        let lock = Lock::new(0usize);
+        std::thread::scope(|scope| {
+            for i in 0..cnt {
+                let lock = &lock;
+                scope.spawn(move || {
+                    if i % 2 == 0 {
+                        let mut guard = lock.lock().unwrap();
+                        let value = *guard;
+                        std::thread::yield_now();
+                        *guard = value + i;
+                    } else {
+                        lock.run(|data| *data += i).unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(*lock.lock().unwrap(), cnt * (cnt - 1) / 2);
+    }
+
+    #[test]
+    fn multi_thread_lock_panic() {
+        let cnt = 100;
+        let lock = Lock::new(0usize);
+        std::thread::scope(|scope| {
+            let handles = (0..cnt)
+                .map(|i| {
+                    let lock = &lock;
+                    scope.spawn(move || {
+                        let mut guard = lock.lock()?;
+                        *guard += 1;
+                        if i == cnt / 2 {
+                            panic!("panic while holding the guard");
+                        }
+                        Ok::<_, crate::LockPoisoned>(())
+                    })
+                })
+                .collect::<std::vec::Vec<_>>();
+            for handle in handles {
+                _ = handle.join();
+            }
+        });
+        assert!(lock.lock().is_err());
+        assert!(lock.run(|_| ()).is_err());
+    }
+}
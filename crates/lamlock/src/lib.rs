@@ -2,20 +2,36 @@
 #![cfg_attr(all(feature = "nightly", not(miri)), allow(internal_features))]
 #![cfg_attr(all(feature = "nightly", not(miri)), feature(core_intrinsics))]
 #![doc = include_str!("../README.md")]
-use core::{
-    cell::{Cell, UnsafeCell},
-    mem::MaybeUninit,
-    ops::ControlFlow,
-    ptr::NonNull,
-    sync::atomic::Ordering,
-};
-
-use crate::node::Node;
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+#[cfg(all(feature = "futex", feature = "spin-only"))]
+compile_error!("the `spin-only` feature replaces `futex`, disable default features to use it");
+#[cfg(not(any(feature = "futex", feature = "spin-only")))]
+compile_error!("either the `futex` or the `spin-only` feature must be enabled");
+use core::{cell::UnsafeCell, ops::ControlFlow, sync::atomic::Ordering};
+
+use crate::combined::CombinedNode;
+mod backoff;
 mod bomb;
+mod combined;
 mod futex;
+#[cfg(feature = "std")]
+mod guard;
 mod node;
+mod project;
+#[cfg(feature = "lock-api")]
+mod raw_mutex;
 mod rawlock;
 
+#[cfg(feature = "std")]
+pub use guard::Guard;
+pub use project::Projected;
+#[cfg(feature = "lock-api")]
+pub use raw_mutex::{Mutex, MutexGuard, RawMutex};
+
 /// Error type for when a lock is poisoned.
 #[derive(Debug, Clone, Copy, Default)]
 pub struct LockPoisoned;
@@ -26,6 +42,16 @@ pub struct LockNotPoisoned;
 
 pub type LockResult<T> = Result<T, LockPoisoned>;
 
+/// Why a lock was poisoned, see [`Lock::poison_kind`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PoisonKind {
+    /// A critical section panicked.
+    Panic,
+    /// The lock was poisoned with [`Lock::poison`] or [`Lock::try_poison`].
+    Explicit,
+}
+
 impl core::fmt::Display for LockPoisoned {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "Lock is poisoned")
@@ -42,64 +68,307 @@ impl core::fmt::Display for LockNotPoisoned {
 
 impl core::error::Error for LockNotPoisoned {}
 
+/// Error type for [`Lock::run_until`].
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutError {
+    /// The lock is poisoned.
+    Poisoned,
+    /// The deadline passed before the lock could be acquired. The closure has not been run.
+    TimedOut,
+}
+
+#[cfg(feature = "std")]
+impl From<LockPoisoned> for TimeoutError {
+    fn from(_: LockPoisoned) -> Self {
+        TimeoutError::Poisoned
+    }
+}
+
+#[cfg(feature = "std")]
+impl core::fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            TimeoutError::Poisoned => write!(f, "Lock is poisoned"),
+            TimeoutError::TimedOut => write!(f, "Lock acquisition timed out"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl core::error::Error for TimeoutError {}
+
+/// Controls what happens when a closure scheduled with [`Lock::run`] panics.
+/// See [`Lock::with_panic_policy`].
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PanicPolicy {
+    /// Poison the lock and notify all waiters. This is the default.
+    #[default]
+    Poison,
+    /// Catch the panic, report it to the caller of the panicking closure only, and keep the lock usable.
+    Isolate,
+}
+
+/// Counters describing how effective combining is, see [`Lock::combine_stats`].
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CombineStats {
+    /// Number of times the lock was taken, either on the fast path, by a combiner or by a guard.
+    pub acquisitions: u64,
+    /// Number of critical sections run under those acquisitions.
+    pub combined: u64,
+}
+
+#[cfg(feature = "metrics")]
+impl CombineStats {
+    /// Average number of critical sections run per acquisition.
+    pub fn average_batch(&self) -> f64 {
+        if self.acquisitions == 0 {
+            return 0.0;
+        }
+        self.combined as f64 / self.acquisitions as f64
+    }
+}
+
+/// Controls whether a newly arriving thread may take the lock ahead of queued ones.
+/// See [`Lock::with_fairness`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Fairness {
+    /// Take the lock directly whenever it is free and nobody is queued.
+    /// A thread may occasionally barge ahead of a waiter that is about to take the lock.
+    /// This is the default and gives the best throughput.
+    #[default]
+    Barging,
+    /// Always enqueue, so that closures run strictly in the order their threads joined the queue.
+    Fifo,
+}
+
 /// The `Lock` struct is a thread-safe, poisonable lock that allows for safe concurrent access to data.
 /// Create a new `Lock` with the [`Lock::new`] method.
 /// To get access to the data, you can use the [`Lock::run`] method.
-pub struct Lock<T> {
+///
+/// The data may be unsized: a `&Lock<[T; N]>` coerces to `&Lock<[T]>`, and likewise for trait objects.
+/// ```rust
+/// use lamlock::Lock;
+/// let lock = Lock::new([0u8; 4]);
+/// let slice: &Lock<[u8]> = &lock;
+/// slice.run(|data| data.fill(1)).unwrap();
+/// assert_eq!(lock.run(|data| *data).unwrap(), [1; 4]);
+/// ```
+pub struct Lock<T: ?Sized> {
     raw: rawlock::RawLock,
+    fairness: Fairness,
+    #[cfg(feature = "std")]
+    panic_policy: PanicPolicy,
     data: UnsafeCell<T>,
 }
 
-unsafe impl<T: Send> Sync for Lock<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Lock<T> {}
+
+impl<T: Default> Default for Lock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> From<T> for Lock<T> {
+    fn from(data: T) -> Self {
+        Self::new(data)
+    }
+}
 
 impl<T> Lock<T> {
     /// Create a new lock with the given data.
     pub const fn new(data: T) -> Self {
         Self {
             raw: rawlock::RawLock::new(),
+            fairness: Fairness::Barging,
+            #[cfg(feature = "std")]
+            panic_policy: PanicPolicy::Poison,
             data: UnsafeCell::new(data),
         }
     }
-    /// Wait until the lock is available, then poison it.
+
+    /// Set the fairness policy of the lock. The default is [`Fairness::Barging`].
+    ///
+    /// With [`Fairness::Fifo`], the fast path that takes a free lock without enqueueing is disabled.
+    /// Every closure goes through the queue, which prevents waiters from being starved by newly
+    /// arriving threads, at the cost of always paying for the queue.
+    /// ```rust
+    /// use lamlock::{Fairness, Lock};
+    /// let lock = Lock::new(0).with_fairness(Fairness::Fifo);
+    /// lock.run(|data| *data += 1).unwrap();
+    /// assert_eq!(lock.run(|x| *x).unwrap(), 1);
+    /// ```
+    pub const fn with_fairness(mut self, fairness: Fairness) -> Self {
+        self.fairness = fairness;
+        self
+    }
+
+    /// Limit how many closures a combiner runs before handing the lock over. There is no limit by default.
+    ///
+    /// Under sustained contention, the thread that becomes the combiner keeps running the closures of
+    /// queued threads, and may not return for a long time. With a limit of `n`, the combiner runs at most
+    /// `n` closures, including its own, and then hands the lock to the next queued thread, which takes over
+    /// combining. This bounds the latency of the combiner at the cost of more handoffs.
+    ///
+    /// # Panics
+    /// Panics if `limit` is zero.
+    /// ```rust
+    /// use lamlock::Lock;
+    /// let lock = Lock::new(0).with_combine_limit(8);
+    /// lock.run(|data| *data += 1).unwrap();
+    /// assert_eq!(lock.run(|x| *x).unwrap(), 1);
+    /// ```
+    pub const fn with_combine_limit(mut self, limit: usize) -> Self {
+        assert!(limit > 0, "combine limit must be positive");
+        self.raw.set_combine_limit(limit);
+        self
+    }
+
+    /// Set the panic policy of the lock. The default is [`PanicPolicy::Poison`].
+    ///
+    /// With [`PanicPolicy::Isolate`], a panic in a closure scheduled with [`Lock::run`] (or a method built on it,
+    /// such as [`Lock::replace`]), [`Lock::run_boxed`], [`Lock::run_local`] or [`Lock::run_until`] is caught where the closure runs, on the caller's thread or on a combiner.
+    /// Only the caller of that closure gets [`LockPoisoned`]; the lock stays usable and the other closures in the
+    /// same batch run normally. The data is left as the closure left it when it panicked, so only use this
+    /// policy if closures keep the data consistent even when interrupted. Other entry points, e.g. [`Lock::lock`]
+    /// or [`Lock::broadcast`], still poison the lock.
+    /// ```rust
+    /// use lamlock::{Lock, PanicPolicy};
+    /// let lock = Lock::new(0).with_panic_policy(PanicPolicy::Isolate);
+    /// assert!(lock.run(|_| panic!("isolated")).is_err());
+    /// assert_eq!(lock.run(|x| *x + 1).unwrap(), 1);
+    /// ```
+    #[cfg(feature = "std")]
+    pub const fn with_panic_policy(mut self, panic_policy: PanicPolicy) -> Self {
+        self.panic_policy = panic_policy;
+        self
+    }
+
+    /// Replace the protected value, returning the old one.
+    /// This is a shorthand for [`Lock::run`] with [`core::mem::replace`].
+    /// ```rust
+    /// use lamlock::Lock;
+    /// let lock = Lock::new(1);
+    /// assert_eq!(lock.replace(2).unwrap(), 1);
+    /// assert_eq!(lock.run(|x| *x).unwrap(), 2);
+    /// ```
+    pub fn replace(&self, value: T) -> LockResult<T>
+    where
+        T: Send,
+    {
+        self.run(move |data| core::mem::replace(data, value))
+    }
+
+    /// Take the protected value, leaving [`Default::default`] in its place.
+    /// This is a shorthand for [`Lock::run`] with [`core::mem::take`].
+    pub fn take(&self) -> LockResult<T>
+    where
+        T: Default + Send,
+    {
+        self.run(core::mem::take)
+    }
+
+    /// Read a copy of the data without taking the lock.
+    ///
+    /// This works like a seqlock: the data is copied while the lock is free, and the copy is
+    /// discarded and retried if the lock was taken in the meantime. Readers never block each other
+    /// or the combiner, but may have to retry while the lock is busy.
+    /// Intended for small [`Copy`] values such as counters, where copying is cheap.
+    ///
+    /// Return an error if the lock is poisoned.
+    ///
+    /// # Safety
+    /// No critical section may modify the data while this call is in progress. The copy is made with plain
+    /// reads, so a concurrent write is a data race, which is undefined behavior even though the torn copy
+    /// would be discarded. Critical sections that only read the data may run concurrently.
+    /// ```rust
+    /// use lamlock::Lock;
+    /// let lock = Lock::new((1, 1));
+    /// lock.run(|data| *data = (2, 2)).unwrap();
+    /// // SAFETY: no other thread runs a critical section.
+    /// assert_eq!(unsafe { lock.read_snapshot() }.unwrap(), (2, 2));
+    /// ```
+    pub unsafe fn read_snapshot(&self) -> LockResult<T>
+    where
+        T: Copy,
+    {
+        let mut backoff = backoff::Backoff::new();
+        loop {
+            if let Some(status) = self.raw.read_begin()? {
+                // The caller guarantees that nobody writes the data, the validation only rejects
+                // copies made while the lock was taken.
+                let snapshot = unsafe {
+                    self.data
+                        .get()
+                        .cast::<core::mem::MaybeUninit<T>>()
+                        .read_volatile()
+                };
+                if self.raw.read_validate(status) {
+                    return Ok(unsafe { snapshot.assume_init() });
+                }
+            }
+            backoff.snooze();
+        }
+    }
+}
+
+impl<T: ?Sized> Lock<T> {
+    /// Wait for our turn in the queue, then poison the lock.
     /// Return error if the lock is already poisoned.
+    ///
+    /// Closures queued ahead still run. Those queued behind are woken right away and fail with
+    /// [`LockPoisoned`], the same way as when a critical section panics.
     pub fn poison(&self) -> Result<(), LockPoisoned> {
-        self.raw.acquire()?;
-        self.raw.poison();
+        self.raw.check_reentrancy();
+        let node = node::Node::holder();
+        let this = core::ptr::NonNull::from(&node);
+        node::Node::acquire(this, &self.raw)?;
+        // The lock is held on behalf of the node, so the bomb can walk the rest of the queue.
+        drop(bomb::HeavyWeightBomb::with_kind(
+            &self.raw,
+            this,
+            PoisonKind::Explicit,
+        ));
         Ok(())
     }
 
+    /// Poison the lock if it can be acquired without waiting.
+    /// Return `Ok(true)` if the lock has been poisoned, `Ok(false)` if it is currently held,
+    /// and an error if it is already poisoned.
+    /// ```rust
+    /// use lamlock::Lock;
+    /// let lock = Lock::new(0);
+    /// assert!(lock.try_poison().unwrap());
+    /// assert!(lock.try_poison().is_err());
+    /// ```
+    pub fn try_poison(&self) -> Result<bool, LockPoisoned> {
+        if !self.raw.try_acquire()? {
+            return Ok(false);
+        }
+        self.raw.poison(PoisonKind::Explicit);
+        Ok(true)
+    }
+
     #[inline(never)]
     fn run_slowly<F, R>(&self, f: F) -> LockResult<R>
     where
         F: FnOnce(&mut T) -> R + Send,
         R: Send,
     {
-        #[repr(C)]
-        struct CombinedNode<'a, T, F, R> {
-            node: Node,
-            closure: MaybeUninit<F>,
-            data: &'a UnsafeCell<T>,
-            result: Cell<MaybeUninit<R>>,
-        }
-        unsafe fn execute<T, F, R>(this: NonNull<Node>)
-        where
-            F: FnOnce(&mut T) -> R,
-        {
-            let this = this.cast::<CombinedNode<T, F, R>>();
-            let closure = unsafe { this.as_ref().closure.assume_init_read() };
-            let data = unsafe { &mut *this.as_ref().data.get() };
-            let result = (closure)(data);
-            unsafe { this.as_ref().result.set(MaybeUninit::new(result)) };
-        }
-        let combined_node = CombinedNode {
-            node: Node::new(execute::<T, F, R>),
-            closure: MaybeUninit::new(f),
-            data: &self.data,
-            result: Cell::new(MaybeUninit::uninit()),
-        };
-        let this = NonNull::from(&combined_node).cast();
-        Node::attach(this, &self.raw)?;
-        Ok(unsafe { combined_node.result.into_inner().assume_init() })
+        self.raw.record_path(false);
+        CombinedNode::new(f, &self.data).attach(&self.raw)
+    }
+
+    #[inline(never)]
+    fn broadcast_slowly<R>(&self, f: fn(&mut T) -> R) -> LockResult<R>
+    where
+        R: Clone + Send + 'static,
+    {
+        self.raw.record_path(false);
+        CombinedNode::shared(f, &self.data).attach(&self.raw)
     }
 
     /// Schedules a closure to run on the lock's data.
@@ -120,14 +389,382 @@ impl<T> Lock<T> {
         F: FnOnce(&mut T) -> R + Send,
         R: Send,
     {
-        if !self.raw.has_tail(Ordering::Relaxed) && self.raw.try_acquire()? {
-            let bomb = bomb::LightWeightBomb::new(&self.raw);
-            let result = f(unsafe { &mut *self.data.get() });
-            self.raw.release();
-            bomb.diffuse();
-            return Ok(result);
+        #[cfg(feature = "std")]
+        if self.panic_policy == PanicPolicy::Isolate {
+            return self.run_isolated(f);
+        }
+        match self.try_run_fast(f)? {
+            Ok(result) => Ok(result),
+            Err(f) => self.run_slowly(f),
         }
-        self.run_slowly(f)
+    }
+
+    /// Same as [`Lock::run`], but a panic in the closure is caught wherever the closure runs.
+    #[cfg(feature = "std")]
+    #[inline(never)]
+    fn run_isolated<F, R>(&self, f: F) -> LockResult<R>
+    where
+        F: FnOnce(&mut T) -> R + Send,
+        R: Send,
+    {
+        let result = match self.try_run_fast(isolate(f))? {
+            Ok(result) => result,
+            Err(f) => self.run_slowly(f)?,
+        };
+        result.ok_or(LockPoisoned)
+    }
+
+    /// Run the closure on the current thread if the lock can be taken right away.
+    ///
+    /// Returns `Ok(None)` without running the closure if the lock is busy (or the lock uses
+    /// [`Fairness::Fifo`]). Since this method never falls back to combining, neither the closure
+    /// nor its result has to be [`Send`].
+    /// ```rust
+    /// use lamlock::Lock;
+    /// use std::rc::Rc;
+    /// let lock = Lock::new(1);
+    /// let shared = lock.run_local(|data| Rc::new(*data)).unwrap();
+    /// assert_eq!(shared.as_deref(), Some(&1));
+    /// ```
+    pub fn run_local<F, R>(&self, f: F) -> LockResult<Option<R>>
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        #[cfg(feature = "std")]
+        if self.panic_policy == PanicPolicy::Isolate {
+            return match self.try_run_fast(isolate(f))? {
+                Ok(result) => result.map(Some).ok_or(LockPoisoned),
+                Err(_) => Ok(None),
+            };
+        }
+        Ok(self.try_run_fast(f)?.ok())
+    }
+
+    /// Schedules a read-only closure to run on the lock's data.
+    ///
+    /// Closures are still serialized with all other accesses, exactly like [`Lock::run`]. Using this
+    /// method for readers states the intent and lets future versions give readers a cheaper path.
+    /// ```rust
+    /// use lamlock::Lock;
+    /// let lock = Lock::new(String::from("config"));
+    /// assert_eq!(lock.run_ref(|data| data.len()).unwrap(), 6);
+    /// ```
+    #[inline(always)]
+    pub fn run_ref<F, R>(&self, f: F) -> LockResult<R>
+    where
+        F: FnOnce(&T) -> R + Send,
+        R: Send,
+    {
+        self.run(move |data| f(data))
+    }
+
+    /// Run `pred` on the lock's data, and `f` only if it returns `true`, both within one critical section.
+    ///
+    /// No other closure runs between the check and the update, so this works like a compare-and-swap
+    /// on arbitrary data. Return `None` if `pred` returned `false`.
+    /// ```rust
+    /// use lamlock::Lock;
+    /// let lock = Lock::new(1);
+    /// assert_eq!(lock.compare_and_run(|x| *x == 1, |x| *x = 2).unwrap(), Some(()));
+    /// assert_eq!(lock.compare_and_run(|x| *x == 1, |x| *x = 3).unwrap(), None);
+    /// assert_eq!(lock.run(|x| *x).unwrap(), 2);
+    /// ```
+    #[inline(always)]
+    pub fn compare_and_run<P, F, R>(&self, pred: P, f: F) -> LockResult<Option<R>>
+    where
+        P: FnOnce(&T) -> bool + Send,
+        F: FnOnce(&mut T) -> R + Send,
+        R: Send,
+    {
+        self.run(move |data| if pred(data) { Some(f(data)) } else { None })
+    }
+
+    /// Run `under_lock` on the lock's data like [`Lock::run`], then pass its result to `after`
+    /// once the lock has been released.
+    ///
+    /// This keeps the critical section as short as possible: copy out what is needed in `under_lock`
+    /// and do the expensive work in `after`. `after` runs on the calling thread without the lock,
+    /// so it cannot touch the protected data. It is not called if the lock is poisoned.
+    /// ```rust
+    /// use lamlock::Lock;
+    /// let lock = Lock::new(vec![3, 1, 2]);
+    /// let sorted = lock
+    ///     .run_then(|data| data.clone(), |mut copy| {
+    ///         copy.sort();
+    ///         copy
+    ///     })
+    ///     .unwrap();
+    /// assert_eq!(sorted, [1, 2, 3]);
+    /// ```
+    #[inline(always)]
+    pub fn run_then<F, G, A, R>(&self, under_lock: F, after: G) -> LockResult<R>
+    where
+        F: FnOnce(&mut T) -> A + Send,
+        G: FnOnce(A) -> R,
+        A: Send,
+    {
+        self.run(under_lock).map(after)
+    }
+
+    /// Same as [`Lock::run`], but the closure is moved to the heap if it has to be enqueued.
+    ///
+    /// On the slow path, [`Lock::run`] stores the whole closure inside a node on the caller's stack.
+    /// For closures capturing large values (e.g. big arrays), this may overflow the stack of the waiting
+    /// thread. This variant keeps only a pointer in the node, at the cost of one heap allocation when
+    /// the lock is contended. The fast path does not allocate.
+    /// ```rust
+    /// use lamlock::Lock;
+    /// let lock = Lock::new(0usize);
+    /// let payload = [1u8; 4096];
+    /// lock.run_boxed(move |data| {
+    ///   *data += payload.len();
+    /// }).unwrap();
+    /// assert_eq!(lock.run(|x| *x).unwrap(), 4096);
+    /// ```
+    #[cfg(feature = "alloc")]
+    pub fn run_boxed<F, R>(&self, f: F) -> LockResult<R>
+    where
+        F: FnOnce(&mut T) -> R + Send,
+        R: Send,
+    {
+        #[cfg(feature = "std")]
+        if self.panic_policy == PanicPolicy::Isolate {
+            return self.run_boxed_inner(isolate(f))?.ok_or(LockPoisoned);
+        }
+        self.run_boxed_inner(f)
+    }
+
+    #[cfg(feature = "alloc")]
+    fn run_boxed_inner<F, R>(&self, f: F) -> LockResult<R>
+    where
+        F: FnOnce(&mut T) -> R + Send,
+        R: Send,
+    {
+        match self.try_run_fast(f)? {
+            Ok(result) => Ok(result),
+            Err(f) => {
+                let boxed = alloc::boxed::Box::new(f);
+                self.run_slowly(move |data| boxed(data))
+            }
+        }
+    }
+
+    /// Schedules a closure whose result is shared with other waiters.
+    ///
+    /// This works like [`Lock::run`], except that queued `broadcast` calls running the same function
+    /// right behind each other are served by a single execution: the combiner runs the first one and hands
+    /// a clone of its result to the following ones, which are not run. The function is a plain `fn`
+    /// (or a closure capturing nothing), so that its result only depends on the data, e.g.
+    /// "initialize if empty, then return a snapshot". Closures capturing state cannot be shared safely,
+    /// since callers passing different arguments would receive each other's results.
+    /// ```rust
+    /// use lamlock::Lock;
+    /// let lock = Lock::new(Vec::new());
+    /// let len = lock.broadcast(|data| {
+    ///   if data.is_empty() {
+    ///     data.extend(0..16);
+    ///   }
+    ///   data.len()
+    /// }).unwrap();
+    /// assert_eq!(len, 16);
+    /// ```
+    pub fn broadcast<R>(&self, f: fn(&mut T) -> R) -> LockResult<R>
+    where
+        R: Clone + Send + 'static,
+    {
+        match self.try_run_fast(f)? {
+            Ok(result) => Ok(result),
+            Err(f) => self.broadcast_slowly(f),
+        }
+    }
+
+    /// Block until the lock is acquired and return a guard giving access to the data.
+    /// The lock is released when the guard is dropped, and poisoned if it is dropped during a panic.
+    ///
+    /// Guards and closures scheduled with [`Lock::run`] can be mixed freely: a guard waits in the
+    /// same queue as the closures, and a combiner reaching it hands the lock over instead of
+    /// running anything on its behalf. Holding a guard therefore stalls combining until it is dropped.
+    /// ```rust
+    /// use lamlock::Lock;
+    /// let lock = Lock::new(Vec::new());
+    /// {
+    ///   let mut guard = lock.lock().unwrap();
+    ///   guard.push(1);
+    /// }
+    /// lock.run(|data| data.push(2)).unwrap();
+    /// assert_eq!(*lock.lock().unwrap(), [1, 2]);
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn lock(&self) -> LockResult<Guard<'_, T>> {
+        Guard::new(self)
+    }
+
+    /// Return the combining counters of the lock. The counters are updated with relaxed ordering,
+    /// so a snapshot taken while other threads use the lock may be slightly inconsistent.
+    /// ```rust
+    /// use lamlock::Lock;
+    /// let lock = Lock::new(0);
+    /// lock.run(|data| *data += 1).unwrap();
+    /// let stats = lock.combine_stats();
+    /// assert_eq!(stats.acquisitions, 1);
+    /// assert_eq!(stats.combined, 1);
+    /// ```
+    #[cfg(feature = "metrics")]
+    pub fn combine_stats(&self) -> CombineStats {
+        self.raw.combine_stats()
+    }
+
+    /// Return how many closures passed to [`Lock::run`] and its variants were run directly by their caller
+    /// (the fast path) and how many were enqueued for a combiner (the slow path), as `(fast, slow)`.
+    ///
+    /// A lock that mostly takes the fast path is rarely contended, and a plain mutex may serve it better.
+    /// ```rust
+    /// use lamlock::Lock;
+    /// let lock = Lock::new(0);
+    /// lock.run(|data| *data += 1).unwrap();
+    /// assert_eq!(lock.path_stats(), (1, 0));
+    /// ```
+    #[cfg(feature = "metrics")]
+    pub fn path_stats(&self) -> (u64, u64) {
+        self.raw.path_stats()
+    }
+
+    /// Take the lock without enqueueing if it is free, nobody is queued and the fairness policy allows it.
+    #[inline(always)]
+    fn try_barge(&self) -> LockResult<bool> {
+        if self.fairness == Fairness::Fifo {
+            return Ok(false);
+        }
+        Ok(!self.raw.has_tail(Ordering::Relaxed) && self.raw.try_acquire()?)
+    }
+
+    /// Run the closure directly if the lock can be acquired without waiting.
+    /// Otherwise, the closure is handed back to the caller.
+    #[inline(always)]
+    fn try_run_fast<F, R>(&self, f: F) -> LockResult<Result<R, F>>
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        self.raw.check_reentrancy();
+        if self.try_barge()? {
+            self.raw.record_path(true);
+            return Ok(Ok(self.run_acquired(f)));
+        }
+        Ok(Err(f))
+    }
+
+    /// Run the closure on the current thread while holding a lock taken without enqueueing, then release it.
+    #[inline(always)]
+    fn run_acquired<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        let bomb = bomb::LightWeightBomb::new(&self.raw);
+        self.raw.enter_critical();
+        let result = f(unsafe { &mut *self.data.get() });
+        self.raw.exit_critical();
+        self.raw.record_batch(1);
+        self.raw.release();
+        bomb.diffuse();
+        result
+    }
+
+    /// Run the closure if the lock can be acquired before `deadline`.
+    ///
+    /// This is **not** a queued timed wait: the closure never joins the queue, so no combiner ever sees it
+    /// and there is no cancellation to order against one. Instead, the calling thread polls until the lock
+    /// can be taken the same way as on the fast path of [`Lock::run`], backing off between attempts and
+    /// sleeping at most until the deadline, then runs the closure itself. Once this method returns
+    /// [`TimeoutError::TimedOut`], the closure is dropped without having run.
+    ///
+    /// Since it does not queue, a caller using `run_until` may keep losing to queued closures under heavy
+    /// contention. With [`Fairness::Fifo`], which never lets a closure bypass the queue, the lock is never
+    /// taken and this always times out. A panic in the closure is handled according to the
+    /// [`PanicPolicy`] of the lock.
+    /// ```rust
+    /// use lamlock::Lock;
+    /// use std::time::{Duration, Instant};
+    /// let lock = Lock::new(0);
+    /// let deadline = Instant::now() + Duration::from_millis(10);
+    /// lock.run_until(|data| *data += 1, deadline).unwrap();
+    /// assert_eq!(lock.run(|x| *x).unwrap(), 1);
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn run_until<F, R>(&self, f: F, deadline: std::time::Instant) -> Result<R, TimeoutError>
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        if self.panic_policy == PanicPolicy::Isolate {
+            return self
+                .run_until_inner(isolate(f), deadline)?
+                .ok_or(TimeoutError::Poisoned);
+        }
+        self.run_until_inner(f, deadline)
+    }
+
+    #[cfg(feature = "std")]
+    fn run_until_inner<F, R>(&self, f: F, deadline: std::time::Instant) -> Result<R, TimeoutError>
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        use std::time::{Duration, Instant};
+        const MAX_PAUSE: Duration = Duration::from_millis(1);
+
+        self.raw.check_reentrancy();
+        let mut backoff = backoff::Backoff::new();
+        let mut pause = Duration::from_micros(1);
+        loop {
+            if self.try_barge()? {
+                return Ok(self.run_acquired(f));
+            }
+            // Under `Fairness::Fifo`, the lock is not even tried, so check for poison separately.
+            if self.raw.poison_kind().is_some() {
+                return Err(TimeoutError::Poisoned);
+            }
+            // Recompute the remaining time after every attempt, so that oversleeping or
+            // spurious wakeups never extend the wait past the deadline.
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(TimeoutError::TimedOut);
+            }
+            if !backoff.is_completed() {
+                backoff.snooze();
+                continue;
+            }
+            std::thread::sleep(pause.min(deadline - now));
+            pause = (pause * 2).min(MAX_PAUSE);
+        }
+    }
+
+    /// Create a view that runs closures on a part of the data.
+    /// All accesses still go through this lock; the projection is applied inside the critical section.
+    /// ```rust
+    /// use lamlock::Lock;
+    /// let lock = Lock::new((0, String::new()));
+    /// let counter = lock.project(|data| &mut data.0);
+    /// counter.run(|x| *x += 1).unwrap();
+    /// assert_eq!(lock.run(|data| data.0).unwrap(), 1);
+    /// ```
+    pub fn project<U, F>(&self, f: F) -> Projected<'_, T, U, F>
+    where
+        U: ?Sized,
+        F: Fn(&mut T) -> &mut U,
+    {
+        Projected::new(self, f)
+    }
+
+    /// Return why the lock is poisoned, or `None` if it is not poisoned.
+    ///
+    /// [`Lock::inspect_poison`] keeps the kind when the lock stays poisoned.
+    /// ```rust
+    /// use lamlock::{Lock, PoisonKind};
+    /// let lock = Lock::new(0);
+    /// assert_eq!(lock.poison_kind(), None);
+    /// lock.poison().unwrap();
+    /// assert_eq!(lock.poison_kind(), Some(PoisonKind::Explicit));
+    /// ```
+    pub fn poison_kind(&self) -> Option<PoisonKind> {
+        self.raw.poison_kind()
     }
 
     /// Try to inspect a poisoned lock. If the input closure returns [`ControlFlow::Continue`], the lock
@@ -148,19 +785,39 @@ impl<T> Lock<T> {
     where
         F: FnOnce(&mut T) -> ControlFlow<R, R>,
     {
+        self.raw.check_reentrancy();
         self.raw.acquire_poison()?;
+        self.raw.enter_critical();
         match f(unsafe { &mut *self.data.get() }) {
             ControlFlow::Continue(result) => {
-                self.raw.poison();
+                self.raw.repoison();
                 Ok(result)
             }
             ControlFlow::Break(result) => {
+                self.raw.exit_critical();
                 self.raw.release();
                 Ok(result)
             }
         }
     }
 
+    /// Run the closure on the data of a poisoned lock, then unpoison the lock and return the result.
+    /// This is the same as calling [`Lock::inspect_poison`] with a closure that always returns [`ControlFlow::Break`].
+    /// ```rust
+    /// use lamlock::Lock;
+    /// let lock = Lock::new(vec![1, 2]);
+    /// lock.poison().unwrap();
+    /// let snapshot = lock.recover(|data| core::mem::take(data)).unwrap();
+    /// assert_eq!(snapshot, [1, 2]);
+    /// assert!(lock.run(|data| data.is_empty()).unwrap());
+    /// ```
+    pub fn recover<F, R>(&self, f: F) -> Result<R, LockNotPoisoned>
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        self.inspect_poison(|data| ControlFlow::Break(f(data)))
+    }
+
     /// Unpoison the lock if it is poisoned.
     /// This is the same of calling [`Lock::inspect_poison`] with a closure that returns [`ControlFlow::Break`] with unit type.
     pub fn unpoison(&self) -> Result<(), LockNotPoisoned> {
@@ -168,6 +825,56 @@ impl<T> Lock<T> {
     }
 }
 
+/// Run `f` on the data of each lock in `locks`, passing the index of the lock in the slice.
+///
+/// The locks are taken one after another in the order of their addresses, no matter the order of the slice.
+/// This is **not** a single critical section: each lock is released before the next one is taken, so other
+/// threads may observe some locks updated and others not. The consistent order keeps threads that call
+/// `run_all` with overlapping sets from interleaving their updates in opposite orders.
+///
+/// Stops at the first poisoned lock and returns [`LockPoisoned`]; `f` has then already run on the locks
+/// visited before it.
+/// ```rust
+/// use lamlock::{Lock, run_all};
+/// let a = Lock::new(1);
+/// let b = Lock::new(2);
+/// run_all(&[&a, &b], |i, data| *data += i).unwrap();
+/// assert_eq!(a.run(|x| *x).unwrap(), 1);
+/// assert_eq!(b.run(|x| *x).unwrap(), 3);
+/// ```
+pub fn run_all<T, F>(locks: &[&Lock<T>], f: F) -> LockResult<()>
+where
+    T: Send,
+    F: Fn(usize, &mut T) + Sync,
+{
+    // Selecting the next lock in place avoids allocating for a sorted copy.
+    let key = |index: usize| (core::ptr::from_ref(locks[index]).addr(), index);
+    let mut last = None;
+    for _ in 0..locks.len() {
+        let Some(index) = (0..locks.len())
+            .filter(|&index| last.is_none_or(|last| key(index) > last))
+            .min_by_key(|&index| key(index))
+        else {
+            break;
+        };
+        last = Some(key(index));
+        let f = &f;
+        locks[index].run(move |data| f(index, data))?;
+    }
+    Ok(())
+}
+
+/// Wrap the closure so that a panic is caught where it runs, see [`PanicPolicy::Isolate`].
+/// The wrapped closure returns `None` if the original one panicked.
+#[cfg(feature = "std")]
+fn isolate<T: ?Sized, F, R>(f: F) -> impl FnOnce(&mut T) -> Option<R>
+where
+    F: FnOnce(&mut T) -> R,
+{
+    use std::panic::{AssertUnwindSafe, catch_unwind};
+    move |data: &mut T| catch_unwind(AssertUnwindSafe(|| f(data))).ok()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -184,6 +891,21 @@ mod tests {
         assert_eq!(lock.run(|x| *x).unwrap(), 1);
     }
 
+    #[test]
+    fn default_and_from_test() {
+        #[derive(Default)]
+        struct Counters {
+            hits: Lock<usize>,
+            name: Lock<std::string::String>,
+        }
+        let counters = Counters::default();
+        counters.hits.run(|x| *x += 1).unwrap();
+        assert_eq!(counters.hits.run(|x| *x).unwrap(), 1);
+        assert!(counters.name.run(|x| x.is_empty()).unwrap());
+        let lock: Lock<_> = 42.into();
+        assert_eq!(lock.run(|x| *x).unwrap(), 42);
+    }
+
     #[test]
     fn multi_thread_test() {
         let cnt = 100;
@@ -203,6 +925,23 @@ mod tests {
         assert_eq!(lock.run(|x| *x).unwrap(), cnt * (cnt + 1) / 2);
     }
 
+    #[test]
+    fn multi_thread_run_ref() {
+        let cnt = 100;
+        let lock = Lock::new(0usize);
+        std::thread::scope(|scope| {
+            for i in 0..cnt {
+                let lock = &lock;
+                scope.spawn(move || {
+                    let before = lock.run_ref(|data| *data).unwrap();
+                    lock.run(|data| *data += i).unwrap();
+                    assert!(lock.run_ref(|data| *data).unwrap() >= before + i);
+                });
+            }
+        });
+        assert_eq!(lock.run_ref(|x| *x).unwrap(), cnt * (cnt - 1) / 2);
+    }
+
     #[test]
     #[should_panic]
     fn mutli_thread_panic_chain_test() {
@@ -224,6 +963,261 @@ mod tests {
         });
     }
 
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn multi_thread_run_boxed() {
+        let cnt = 100;
+        let lock = Lock::new(0);
+        std::thread::scope(|scope| {
+            for i in 0..cnt {
+                let lock = &lock;
+                scope.spawn(move || {
+                    let payload = [i; 1024];
+                    lock.run_boxed(move |data| {
+                        *data += payload[0];
+                    })
+                    .unwrap();
+                });
+            }
+        });
+
+        assert_eq!(lock.run(|x| *x).unwrap(), cnt * (cnt - 1) / 2);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "reentrancy detected")]
+    fn reentrancy_test() {
+        let lock = Lock::new(0);
+        lock.run(|_| {
+            lock.run(|data| *data += 1).unwrap();
+        })
+        .unwrap();
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "reentrancy detected")]
+    fn reentrancy_inspect_poison_test() {
+        let lock = Lock::new(0);
+        lock.poison().unwrap();
+        lock.recover(|_| {
+            _ = lock.unpoison();
+        })
+        .unwrap();
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "reentrancy detected")]
+    fn reentrancy_recover_in_run_test() {
+        let lock = Lock::new(0);
+        _ = lock.run(|_| {
+            _ = lock.recover(|data| *data += 1);
+        });
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn multi_thread_reentrancy_test() {
+        let lock = Lock::new(0);
+        std::thread::scope(|scope| {
+            let handles = (0..16)
+                .map(|_| {
+                    scope.spawn(|| {
+                        lock.run(|_| {
+                            lock.run(|data| *data += 1).unwrap();
+                        })
+                    })
+                })
+                .collect::<std::vec::Vec<_>>();
+            // The first reentrant closure panics; everyone else observes the poison.
+            let panicked = handles
+                .into_iter()
+                .map(|handle| handle.join())
+                .inspect(|result| assert!(!matches!(result, Ok(Ok(())))))
+                .filter(Result::is_err)
+                .count();
+            assert!(panicked >= 1);
+        });
+        assert!(lock.run(|_| ()).is_err());
+    }
+
+    #[test]
+    fn multi_thread_replace() {
+        let cnt = 100;
+        let lock = Lock::new(std::string::String::new());
+        let collected = std::thread::scope(|scope| {
+            let handles = (0..cnt)
+                .map(|i| {
+                    let lock = &lock;
+                    scope.spawn(move || lock.replace(std::format!("{i}")).unwrap())
+                })
+                .collect::<std::vec::Vec<_>>();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect::<std::vec::Vec<_>>()
+        });
+        let mut values = collected;
+        values.push(lock.take().unwrap());
+        assert!(lock.run(|x| x.is_empty()).unwrap());
+        values.sort();
+        let mut expected = (0..cnt)
+            .map(|i| std::format!("{i}"))
+            .collect::<std::vec::Vec<_>>();
+        expected.push(std::string::String::new());
+        expected.sort();
+        assert_eq!(values, expected);
+    }
+
+    #[test]
+    fn multi_thread_broadcast() {
+        static EXECUTIONS: core::sync::atomic::AtomicUsize =
+            core::sync::atomic::AtomicUsize::new(0);
+        let cnt = 16;
+        let lock = Lock::new(0usize);
+        // Hold the lock until every caller is queued, so that the first one serves all the others.
+        assert!(lock.raw.try_acquire().unwrap());
+        let results = std::thread::scope(|scope| {
+            let handles = (0..cnt)
+                .map(|_| {
+                    let tail = lock.raw.tail();
+                    let lock = &lock;
+                    let handle = scope.spawn(move || {
+                        lock.broadcast(|data| {
+                            EXECUTIONS.fetch_add(1, Ordering::Relaxed);
+                            *data += 1;
+                            *data
+                        })
+                        .unwrap()
+                    });
+                    while lock.raw.tail() == tail {
+                        std::thread::yield_now();
+                    }
+                    handle
+                })
+                .collect::<std::vec::Vec<_>>();
+            lock.raw.release();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect::<std::vec::Vec<_>>()
+        });
+        assert_eq!(EXECUTIONS.load(Ordering::Relaxed), 1);
+        assert_eq!(lock.run(|x| *x).unwrap(), 1);
+        assert!(results.iter().all(|&x| x == 1));
+    }
+
+    #[test]
+    fn multi_thread_broadcast_distinct_functions() {
+        let cnt = 16;
+        let lock = Lock::new(0usize);
+        // Queue calls of two functions alternately behind a held lock.
+        assert!(lock.raw.try_acquire().unwrap());
+        std::thread::scope(|scope| {
+            for i in 0..cnt {
+                let tail = lock.raw.tail();
+                let lock = &lock;
+                scope.spawn(move || {
+                    let (expected, result) = if i % 2 == 0 {
+                        (0, lock.broadcast(|_| 0))
+                    } else {
+                        (1, lock.broadcast(|_| 1))
+                    };
+                    assert_eq!(result.unwrap(), expected);
+                });
+                while lock.raw.tail() == tail {
+                    std::thread::yield_now();
+                }
+            }
+            lock.raw.release();
+        });
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn multi_thread_poison_wakes_queued() {
+        let lock = Lock::new(0usize);
+        let guard = lock.lock().unwrap();
+        std::thread::scope(|scope| {
+            let poisoner = scope.spawn(|| lock.poison());
+            while !lock.raw.has_tail(Ordering::Acquire) {
+                std::thread::yield_now();
+            }
+            // The poisoner is queued, so the waiter lines up behind it.
+            let waiter = scope.spawn(|| lock.run(|data| *data += 1));
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            drop(guard);
+            assert!(poisoner.join().unwrap().is_ok());
+            assert!(waiter.join().unwrap().is_err());
+        });
+        assert!(lock.poison().is_err());
+        lock.unpoison().unwrap();
+        assert_eq!(lock.run(|data| *data).unwrap(), 0);
+    }
+
+    #[test]
+    fn multi_thread_recover() {
+        let cnt = 100;
+        let lock = Lock::new(0usize);
+        assert!(lock.recover(|_| ()).is_err());
+        std::thread::scope(|scope| {
+            for i in 0..cnt {
+                let lock = &lock;
+                scope.spawn(move || {
+                    while lock.run(|data| *data += i).is_err() {
+                        // Another thread may recover first, so losing the race is fine.
+                        _ = lock.recover(|_| ());
+                    }
+                });
+            }
+            lock.poison().unwrap();
+        });
+        let total = lock
+            .recover(|data| *data)
+            .or_else(|_| lock.run(|data| *data))
+            .unwrap();
+        assert_eq!(total, cnt * (cnt - 1) / 2);
+    }
+
+    #[test]
+    fn poison_kind_test() {
+        let lock = Lock::new(0);
+        assert_eq!(lock.poison_kind(), None);
+        std::thread::scope(|scope| {
+            _ = scope
+                .spawn(|| lock.run(|_| panic!("poisoned by a panic")))
+                .join();
+        });
+        assert_eq!(lock.poison_kind(), Some(PoisonKind::Panic));
+        lock.inspect_poison(|_| ControlFlow::Continue(())).unwrap();
+        assert_eq!(lock.poison_kind(), Some(PoisonKind::Panic));
+        lock.unpoison().unwrap();
+        assert_eq!(lock.poison_kind(), None);
+        assert!(lock.try_poison().unwrap());
+        assert_eq!(lock.poison_kind(), Some(PoisonKind::Explicit));
+        lock.unpoison().unwrap();
+        lock.poison().unwrap();
+        assert_eq!(lock.poison_kind(), Some(PoisonKind::Explicit));
+    }
+
+    #[test]
+    fn multi_thread_head_waiter_keeps_poison_kind() {
+        let lock = Lock::new(0);
+        assert!(lock.raw.try_acquire().unwrap());
+        std::thread::scope(|scope| {
+            let waiter = scope.spawn(|| lock.run(|x| *x += 1));
+            // The waiter is the head of the queue, waiting for the lock to be released.
+            while !lock.raw.has_tail(Ordering::Acquire) {
+                std::thread::yield_now();
+            }
+            lock.raw.poison(PoisonKind::Explicit);
+            assert!(waiter.join().unwrap().is_err());
+        });
+        assert_eq!(lock.poison_kind(), Some(PoisonKind::Explicit));
+    }
+
     #[test]
     fn multi_thread_inspect_poison() {
         let lock = Lock::new(std::string::String::new());
@@ -248,7 +1242,462 @@ mod tests {
                 handle.join().unwrap();
             }
             assert_eq!(lock.run(|x| x.len()).unwrap(), 100);
-            assert_eq!(lock.run(|x| x.chars().all(|c| c == 'A')).unwrap(), true);
+            assert!(lock.run(|x| x.chars().all(|c| c == 'A')).unwrap());
+        });
+    }
+
+    #[test]
+    fn multi_thread_unsized() {
+        let cnt = 100;
+        let lock = Lock::new([0usize; 8]);
+        let slice: &Lock<[usize]> = &lock;
+        std::thread::scope(|scope| {
+            for i in 0..cnt {
+                scope.spawn(move || {
+                    slice
+                        .run(|data| {
+                            let len = data.len();
+                            data[i % len] += 1;
+                        })
+                        .unwrap();
+                });
+            }
+        });
+        assert_eq!(lock.run(|x| x.iter().sum::<usize>()).unwrap(), cnt);
+
+        let boxed: std::boxed::Box<Lock<dyn core::fmt::Write + Send>> =
+            std::boxed::Box::new(Lock::new(std::string::String::new()));
+        boxed.run(|w| w.write_str("hello")).unwrap().unwrap();
+    }
+
+    #[test]
+    fn multi_thread_panicking_result_drop() {
+        struct PanicOnDrop;
+        impl Drop for PanicOnDrop {
+            fn drop(&mut self) {
+                panic!("result dropped");
+            }
+        }
+        let cnt = 100;
+        let lock = Lock::new(0);
+        std::thread::scope(|scope| {
+            for _ in 0..cnt {
+                let lock = &lock;
+                scope.spawn(move || {
+                    let result = lock
+                        .run(|data| {
+                            *data += 1;
+                            PanicOnDrop
+                        })
+                        .unwrap();
+                    let dropped = std::panic::catch_unwind(move || drop(result));
+                    assert!(dropped.is_err());
+                });
+            }
+        });
+        // The results are dropped outside of the critical section, so the lock stays healthy.
+        assert_eq!(lock.run(|x| *x).unwrap(), cnt);
+    }
+
+    #[test]
+    fn multi_thread_poisoned_closure_drop() {
+        static DROPS: core::sync::atomic::AtomicUsize = core::sync::atomic::AtomicUsize::new(0);
+        struct Tracker;
+        impl Drop for Tracker {
+            fn drop(&mut self) {
+                DROPS.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        let cnt = 100;
+        let lock = Lock::new(0);
+        std::thread::scope(|scope| {
+            let handles = (0..cnt)
+                .map(|i| {
+                    let lock = &lock;
+                    scope.spawn(move || {
+                        let tracker = Tracker;
+                        _ = lock.run(move |data| {
+                            let _tracker = tracker;
+                            *data += 1;
+                            if i == cnt / 2 {
+                                // Give other threads time to queue up behind the combiner.
+                                std::thread::sleep(std::time::Duration::from_millis(1));
+                                panic!("poison");
+                            }
+                        });
+                    })
+                })
+                .collect::<std::vec::Vec<_>>();
+            for handle in handles {
+                _ = handle.join();
+            }
         });
+        // Every closure is dropped exactly once, whether it ran or was discarded after the poison.
+        assert_eq!(DROPS.load(Ordering::Relaxed), cnt);
+    }
+
+    #[test]
+    fn multi_thread_read_snapshot() {
+        let rounds = if cfg!(miri) { 2 } else { 100 };
+        let lock = Lock::new((0usize, 0usize));
+        for round in 1..=rounds {
+            lock.run(move |data| *data = (round, round)).unwrap();
+            // Snapshots only run concurrently with critical sections that read the data.
+            let done = core::sync::atomic::AtomicBool::new(false);
+            std::thread::scope(|scope| {
+                for _ in 0..4 {
+                    scope.spawn(|| {
+                        while !done.load(Ordering::Relaxed) {
+                            let (a, b) = unsafe { lock.read_snapshot() }.unwrap();
+                            assert_eq!((a, b), (round, round));
+                        }
+                    });
+                }
+                let readers = (0..4)
+                    .map(|_| {
+                        scope.spawn(|| {
+                            for _ in 0..10 {
+                                let (a, b) = lock.run(|data| *data).unwrap();
+                                assert_eq!(a, b);
+                            }
+                        })
+                    })
+                    .collect::<std::vec::Vec<_>>();
+                for reader in readers {
+                    reader.join().unwrap();
+                }
+                done.store(true, Ordering::Relaxed);
+            });
+        }
+        assert_eq!(unsafe { lock.read_snapshot() }.unwrap(), (rounds, rounds));
+        lock.poison().unwrap();
+        assert!(unsafe { lock.read_snapshot() }.is_err());
+        lock.unpoison().unwrap();
+        assert_eq!(unsafe { lock.read_snapshot() }.unwrap(), (rounds, rounds));
+    }
+
+    #[test]
+    fn multi_thread_compare_and_run() {
+        let cnt = 100;
+        let lock = Lock::new(0usize);
+        std::thread::scope(|scope| {
+            for _ in 0..cnt {
+                scope.spawn(|| {
+                    loop {
+                        let current = lock.run(|x| *x).unwrap();
+                        let swapped = lock
+                            .compare_and_run(|x| *x == current, |x| *x = current + 1)
+                            .unwrap();
+                        if swapped.is_some() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        assert_eq!(lock.run(|x| *x).unwrap(), cnt);
+    }
+
+    #[test]
+    fn multi_thread_combine_limit() {
+        let cnt = 100;
+        let limit = 3;
+        let lock = Lock::new(std::vec::Vec::new()).with_combine_limit(limit);
+        std::thread::scope(|scope| {
+            for _ in 0..cnt {
+                scope.spawn(|| {
+                    // Each thread runs a single closure, so it combines at most once.
+                    lock.run(|data| {
+                        data.push(std::thread::current().id());
+                        std::thread::yield_now();
+                    })
+                    .unwrap();
+                });
+            }
+        });
+        let executors = lock.run(core::mem::take).unwrap();
+        assert_eq!(executors.len(), cnt);
+        assert!(
+            executors
+                .chunk_by(|a, b| a == b)
+                .all(|run| run.len() <= limit),
+            "a combiner ran more closures than the limit"
+        );
+    }
+
+    #[test]
+    fn multi_thread_run_all() {
+        let cnt = 100;
+        let a = Lock::new(0usize);
+        let b = Lock::new(0usize);
+        std::thread::scope(|scope| {
+            for i in 0..cnt {
+                let (a, b) = (&a, &b);
+                scope.spawn(move || {
+                    // Half of the threads pass the locks in the opposite order.
+                    let locks = if i % 2 == 0 { [a, b] } else { [b, a] };
+                    run_all(&locks, |_, data| *data += 1).unwrap();
+                });
+            }
+        });
+        assert_eq!(a.run(|x| *x).unwrap(), cnt);
+        assert_eq!(b.run(|x| *x).unwrap(), cnt);
+    }
+
+    #[test]
+    fn run_all_order_test() {
+        let locks = [Lock::new(0usize), Lock::new(0), Lock::new(0)];
+        let order = Lock::new(std::vec::Vec::new());
+        let refs = [&locks[2], &locks[0], &locks[1]];
+        run_all(&refs, |i, data| {
+            *data = i;
+            order.run(|order| order.push(i)).unwrap();
+        })
+        .unwrap();
+        // Locks are visited by address, and each one receives its index in the slice.
+        assert_eq!(order.run(core::mem::take).unwrap(), [1, 2, 0]);
+        assert_eq!(
+            locks.each_ref().map(|lock| lock.run(|x| *x).unwrap()),
+            [1, 2, 0]
+        );
+    }
+
+    #[test]
+    fn run_local_test() {
+        let lock = Lock::new(0);
+        let value = lock
+            .run_local(|data| {
+                *data += 1;
+                std::rc::Rc::new(*data)
+            })
+            .unwrap();
+        assert_eq!(value.as_deref(), Some(&1));
+        // While another thread holds the lock, the closure is not run.
+        let (entered, wait_entered) = std::sync::mpsc::channel();
+        let (leave, wait_leave) = std::sync::mpsc::channel::<()>();
+        std::thread::scope(|scope| {
+            let lock = &lock;
+            scope.spawn(move || {
+                lock.run(move |_| {
+                    entered.send(()).unwrap();
+                    wait_leave.recv().unwrap();
+                })
+                .unwrap();
+            });
+            wait_entered.recv().unwrap();
+            assert_eq!(lock.run_local(|data| *data += 1).unwrap(), None);
+            leave.send(()).unwrap();
+        });
+        assert_eq!(lock.run(|x| *x).unwrap(), 1);
+        lock.poison().unwrap();
+        assert!(lock.run_local(|_| ()).is_err());
+    }
+
+    #[test]
+    fn try_poison_test() {
+        let lock = Lock::new(0);
+        let (entered, wait_entered) = std::sync::mpsc::channel();
+        let (leave, wait_leave) = std::sync::mpsc::channel::<()>();
+        std::thread::scope(|scope| {
+            let lock = &lock;
+            scope.spawn(move || {
+                lock.run(move |data| {
+                    entered.send(()).unwrap();
+                    wait_leave.recv().unwrap();
+                    *data += 1;
+                })
+                .unwrap();
+            });
+            wait_entered.recv().unwrap();
+            // Contended: the watchdog does not wait and does not poison.
+            assert!(!lock.try_poison().unwrap());
+            leave.send(()).unwrap();
+        });
+        assert_eq!(lock.run(|x| *x).unwrap(), 1);
+        assert!(lock.try_poison().unwrap());
+        assert!(lock.run(|_| ()).is_err());
+        assert!(lock.try_poison().is_err());
+    }
+
+    #[test]
+    fn multi_thread_fifo() {
+        let cnt = 32;
+        let late = 8;
+        let lock = Lock::new(std::vec::Vec::new()).with_fairness(Fairness::Fifo);
+        assert!(lock.raw.try_acquire().unwrap());
+        std::thread::scope(|scope| {
+            // Enqueue the waiters one at a time, so that their tickets match the queue order.
+            for ticket in 0..cnt {
+                let tail = lock.raw.tail();
+                let lock = &lock;
+                scope.spawn(move || lock.run(|data| data.push(ticket)).unwrap());
+                while lock.raw.tail() == tail {
+                    std::thread::yield_now();
+                }
+            }
+            lock.raw.release();
+            // Threads arriving while the queue drains must not get ahead of any waiter.
+            for ticket in cnt..cnt + late {
+                let lock = &lock;
+                scope.spawn(move || lock.run(|data| data.push(ticket)).unwrap());
+            }
+        });
+        let executed = lock.take().unwrap();
+        assert_eq!(executed.len(), cnt + late);
+        // Count how many closures with a later ticket ran before each waiter.
+        let max_overtaken = (0..cnt)
+            .map(|ticket| {
+                let position = executed.iter().position(|&t| t == ticket).unwrap();
+                executed[..position].iter().filter(|&&t| t > ticket).count()
+            })
+            .max()
+            .unwrap();
+        assert_eq!(max_overtaken, 0);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn multi_thread_combine_stats() {
+        let cnt = 100;
+        let lock = Lock::new(0usize);
+        std::thread::scope(|scope| {
+            for i in 0..cnt {
+                let lock = &lock;
+                scope.spawn(move || {
+                    lock.run(|data| *data += i).unwrap();
+                });
+            }
+        });
+        let stats = lock.combine_stats();
+        assert_eq!(stats.combined, cnt as u64);
+        let (fast, slow) = lock.path_stats();
+        assert_eq!(fast + slow, cnt as u64);
+        assert!(stats.acquisitions >= 1 && stats.acquisitions <= stats.combined);
+        assert!(stats.average_batch() >= 1.0);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn run_until_test() {
+        use std::time::{Duration, Instant};
+        let lock = Lock::new(0);
+        let (entered, wait_entered) = std::sync::mpsc::channel();
+        let (leave, wait_leave) = std::sync::mpsc::channel::<()>();
+        std::thread::scope(|scope| {
+            let lock = &lock;
+            scope.spawn(move || {
+                lock.run(move |data| {
+                    entered.send(()).unwrap();
+                    wait_leave.recv().unwrap();
+                    *data += 1;
+                })
+                .unwrap();
+            });
+            wait_entered.recv().unwrap();
+            let deadline = Instant::now() + Duration::from_millis(10);
+            let result = lock.run_until(|data| *data += 10, deadline);
+            assert_eq!(result, Err(TimeoutError::TimedOut));
+            assert!(Instant::now() >= deadline);
+            leave.send(()).unwrap();
+            let deadline = Instant::now() + Duration::from_secs(60);
+            lock.run_until(|data| *data += 100, deadline).unwrap();
+        });
+        // The timed out closure never ran.
+        assert_eq!(lock.run(|x| *x).unwrap(), 101);
+        lock.poison().unwrap();
+        let deadline = Instant::now() + Duration::from_millis(10);
+        assert_eq!(
+            lock.run_until(|_| (), deadline),
+            Err(TimeoutError::Poisoned)
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn run_until_fifo_and_isolate() {
+        use std::time::{Duration, Instant};
+        let lock = Lock::new(0).with_fairness(Fairness::Fifo);
+        let deadline = Instant::now() + Duration::from_millis(10);
+        assert_eq!(
+            lock.run_until(|data| *data += 1, deadline),
+            Err(TimeoutError::TimedOut)
+        );
+        assert_eq!(lock.run(|x| *x).unwrap(), 0);
+
+        let lock = Lock::new(0).with_panic_policy(PanicPolicy::Isolate);
+        let deadline = Instant::now() + Duration::from_secs(60);
+        assert_eq!(
+            lock.run_until(|_| panic!("isolated"), deadline),
+            Err::<(), _>(TimeoutError::Poisoned)
+        );
+        lock.run_until(|data| *data += 1, deadline).unwrap();
+        assert_eq!(lock.run(|x| *x).unwrap(), 1);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn multi_thread_isolated_panic() {
+        let cnt = 100;
+        let lock = Lock::new(0usize).with_panic_policy(PanicPolicy::Isolate);
+        let failures = std::thread::scope(|scope| {
+            let handles = (0..cnt)
+                .map(|i| {
+                    let lock = &lock;
+                    scope.spawn(move || {
+                        lock.run(|data| {
+                            if i == cnt / 2 {
+                                panic!("isolated panic");
+                            }
+                            *data += i;
+                        })
+                    })
+                })
+                .collect::<std::vec::Vec<_>>();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .filter(Result::is_err)
+                .count()
+        });
+        // Only the panicking closure fails; everybody else's work is applied.
+        assert_eq!(failures, 1);
+        assert_eq!(lock.run(|x| *x).unwrap(), cnt * (cnt - 1) / 2 - cnt / 2);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn multi_thread_isolated_panic_boxed() {
+        let cnt = 100;
+        let lock = Lock::new(0usize).with_panic_policy(PanicPolicy::Isolate);
+        let failures = std::thread::scope(|scope| {
+            let handles = (0..cnt)
+                .map(|i| {
+                    let lock = &lock;
+                    scope.spawn(move || {
+                        lock.run_boxed(move |data| {
+                            if i == cnt / 2 {
+                                panic!("isolated panic");
+                            }
+                            *data += i;
+                        })
+                    })
+                })
+                .collect::<std::vec::Vec<_>>();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .filter(Result::is_err)
+                .count()
+        });
+        assert_eq!(failures, 1);
+        assert_eq!(lock.run(|x| *x).unwrap(), cnt * (cnt - 1) / 2 - cnt / 2);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn isolated_panic_local() {
+        let lock = Lock::new(0).with_panic_policy(PanicPolicy::Isolate);
+        assert!(lock.run_local(|_| panic!("isolated panic")).is_err());
+        assert_eq!(lock.run_local(|x| *x + 1).unwrap(), Some(1));
     }
 }
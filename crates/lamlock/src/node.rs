@@ -1,21 +1,35 @@
 use core::{
+    any::TypeId,
     ptr::NonNull,
     sync::atomic::{AtomicPtr, Ordering},
 };
 
-use crate::{LockResult, bomb::HeavyWeightBomb, futex, rawlock::RawLock};
+use crate::{LockResult, backoff::Backoff, bomb::HeavyWeightBomb, futex, rawlock::RawLock};
 
-const SPIN_LIMIT: usize = 100;
+pub(crate) const SPIN_LIMIT: usize = 100;
 const WAITING: u32 = 0;
 const DONE: u32 = 1;
 const HEAD: u32 = 2;
 const SLEEPING: u32 = 3;
 pub(crate) const POISONED: u32 = 4;
 
+/// Describes how the result of a node can be handed to its successor instead of running
+/// the successor's closure.
+pub struct Share {
+    /// Nodes can only share results if their keys are equal.
+    pub key: unsafe fn(NonNull<Node>) -> (TypeId, usize),
+    /// Fill the result of the second node from the first one.
+    pub share: unsafe fn(NonNull<Node>, NonNull<Node>),
+}
+
 pub struct Node {
     futex: futex::Futex,
     next: AtomicPtr<Self>,
     closure: unsafe fn(NonNull<Self>),
+    share: Option<&'static Share>,
+    /// A holder node carries no closure: once it reaches the head of the queue, its thread keeps the lock
+    /// until it hands it off explicitly.
+    holder: bool,
 }
 
 impl Node {
@@ -26,7 +40,40 @@ impl Node {
             futex: futex::Futex::new(WAITING),
             next: AtomicPtr::new(core::ptr::null_mut()),
             closure,
+            share: None,
+            holder: false,
+        }
+    }
+
+    /// Creates a new holder node, see [`Node::acquire`].
+    pub const fn holder() -> Self {
+        Self {
+            holder: true,
+            ..Self::new(|_| {})
+        }
+    }
+
+    /// Creates a new `Node` whose result can be shared with compatible successors.
+    pub const fn with_share(closure: unsafe fn(NonNull<Self>), share: &'static Share) -> Self {
+        Self {
+            share: Some(share),
+            ..Self::new(closure)
+        }
+    }
+
+    /// Hand the result of `this` to `next` if both nodes are compatible.
+    /// Return `true` if `next` no longer needs to be executed.
+    unsafe fn try_share(this: NonNull<Self>, next: NonNull<Self>) -> bool {
+        let (Some(src), Some(dst)) = (unsafe { this.as_ref().share }, unsafe {
+            next.as_ref().share
+        }) else {
+            return false;
+        };
+        if unsafe { (src.key)(this) != (dst.key)(next) } {
+            return false;
         }
+        unsafe { (src.share)(this, next) };
+        true
     }
 
     /// Go to sleep until the futex is woken up with a message.
@@ -85,32 +132,19 @@ impl Node {
         self.next.store(next.as_ptr(), Ordering::Release);
     }
 
-    /// Attach the node to a raw lock.
-    pub fn attach(this: NonNull<Self>, raw: &RawLock) -> LockResult<()> {
-        let mut bomb = HeavyWeightBomb::new(raw, this);
+    /// Enqueue the node and wait until it is either served or becomes the head of the queue.
+    /// Return the received message; `HEAD` means the lock is now held on behalf of this node.
+    fn wait_for_turn(this: NonNull<Self>, raw: &RawLock) -> LockResult<u32> {
         match raw.swap_tail(this) {
             Some(prev) => unsafe {
                 prev.as_ref().store_next(this);
-                let mut status;
-                'waiting: {
-                    for _ in 0..SPIN_LIMIT {
-                        status = this.as_ref().futex.load(Ordering::Acquire);
-                        if status != WAITING {
-                            break 'waiting;
-                        }
+                for _ in 0..SPIN_LIMIT {
+                    let status = this.as_ref().futex.load(Ordering::Acquire);
+                    if status != WAITING {
+                        return Ok(status);
                     }
-                    status = this.as_ref().wait();
-                }
-                if status == DONE {
-                    bomb.diffuse();
-                    return Ok(());
                 }
-                if status == POISONED {
-                    // defuse the bomb because we are not the head node.
-                    bomb.diffuse();
-                    return Err(crate::LockPoisoned);
-                }
-                debug_assert_eq!(status, HEAD);
+                Ok(this.as_ref().wait())
             },
             None => {
                 // we are going to be the head node.
@@ -125,50 +159,120 @@ impl Node {
                 // 6. Thread B needs to notify Thread C that the lock is poisoned.
                 // 7. Thread C needs to wake up and handle the poison.
                 raw.acquire()?;
+                Ok(HEAD)
+            }
+        }
+    }
+
+    /// Attach the node to a raw lock.
+    pub fn attach(this: NonNull<Self>, raw: &RawLock) -> LockResult<()> {
+        raw.check_reentrancy();
+        let mut bomb = HeavyWeightBomb::new(raw, this);
+        match Self::wait_for_turn(this, raw)? {
+            DONE => {
+                bomb.diffuse();
+                return Ok(());
             }
+            POISONED => {
+                // defuse the bomb because we are not the head node.
+                bomb.diffuse();
+                return Err(crate::LockPoisoned);
+            }
+            status => debug_assert_eq!(status, HEAD),
         }
+        raw.enter_critical();
         let mut cursor = this;
+        let mut shared = false;
+        let mut batch = 1;
         loop {
             #[cfg(all(feature = "nightly", not(miri)))]
             unsafe {
                 cursor.as_ref().prefetch_next(Ordering::Relaxed);
             }
-            unsafe {
-                (cursor.as_ref().closure)(cursor);
+            if !shared {
+                unsafe {
+                    (cursor.as_ref().closure)(cursor);
+                }
             }
             match unsafe { cursor.as_ref().load_next(Ordering::Acquire) } {
+                Some(next) if unsafe { next.as_ref().holder } || batch >= raw.combine_limit() => {
+                    // The holder runs its critical section on its own thread, so the lock is handed over.
+                    // Past the combine limit, the next node takes over combining the same way.
+                    // This must happen before sharing, since the next node runs its own closure as the head.
+                    raw.exit_critical();
+                    raw.record_batch(batch);
+                    Node::wake_as_head(next);
+                    Node::wake_as_done(cursor);
+                    bomb.diffuse();
+                    return Ok(());
+                }
                 Some(next) => {
+                    shared = unsafe { Node::try_share(cursor, next) };
                     Node::wake_as_done(cursor);
                     cursor = next;
+                    batch += 1;
                     bomb.reset(cursor);
                 }
                 None => break,
             }
         }
+        raw.exit_critical();
+        raw.record_batch(batch);
 
-        if raw.try_close(cursor) {
-            Node::wake_as_done(cursor);
+        Self::handoff(cursor, raw, Node::wake_as_done);
+        bomb.diffuse();
+        Ok(())
+    }
 
+    /// Pass the lock held on behalf of `this` to its successor, or release it if the queue is empty.
+    /// `finish` is invoked on `this` once it is no longer needed.
+    fn handoff(this: NonNull<Self>, raw: &RawLock, finish: fn(NonNull<Self>)) {
+        if raw.try_close(this) {
+            finish(this);
             raw.release();
-            bomb.diffuse();
-            return Ok(());
+            return;
         }
 
+        let mut backoff = Backoff::new();
         loop {
-            match unsafe { cursor.as_ref().load_next(Ordering::Acquire) } {
+            match unsafe { this.as_ref().load_next(Ordering::Acquire) } {
                 Some(next) => {
                     Node::wake_as_head(next);
-                    Node::wake_as_done(cursor);
-                    bomb.diffuse();
-                    return Ok(());
+                    finish(this);
+                    return;
                 }
                 None => {
                     debug_assert!(raw.has_tail(Ordering::SeqCst));
-                    continue;
+                    backoff.snooze();
                 }
             }
         }
     }
+
+    /// Enqueue a holder node and wait until it becomes the head of the queue.
+    /// On success, the lock is held until [`Node::release`] is called with the same node.
+    pub fn acquire(this: NonNull<Self>, raw: &RawLock) -> LockResult<()> {
+        debug_assert!(unsafe { this.as_ref().holder });
+        let bomb = HeavyWeightBomb::new(raw, this);
+        match Self::wait_for_turn(this, raw)? {
+            POISONED => {
+                bomb.diffuse();
+                Err(crate::LockPoisoned)
+            }
+            status => {
+                // Holder nodes are never executed by a combiner.
+                debug_assert_eq!(status, HEAD);
+                bomb.diffuse();
+                Ok(())
+            }
+        }
+    }
+
+    /// Release the lock held by a holder node acquired with [`Node::acquire`].
+    #[cfg(feature = "std")]
+    pub fn release(this: NonNull<Self>, raw: &RawLock) {
+        Self::handoff(this, raw, |_| {});
+    }
 }
 
 #[cfg(test)]
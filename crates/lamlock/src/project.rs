@@ -0,0 +1,70 @@
+use core::marker::PhantomData;
+
+use crate::{Lock, LockResult};
+
+/// A typed view into a part of the data protected by a [`Lock`].
+///
+/// Created by [`Lock::project`]. Closures scheduled through the view still go through the
+/// parent lock, so there is no extra synchronization involved.
+pub struct Projected<'a, T: ?Sized, U: ?Sized, F> {
+    lock: &'a Lock<T>,
+    projection: F,
+    _marker: PhantomData<fn(&mut T) -> &mut U>,
+}
+
+impl<'a, T: ?Sized, U: ?Sized, F> Projected<'a, T, U, F>
+where
+    F: Fn(&mut T) -> &mut U,
+{
+    pub(crate) fn new(lock: &'a Lock<T>, projection: F) -> Self {
+        Self {
+            lock,
+            projection,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Schedules a closure to run on the projected data. See [`Lock::run`] for details.
+    pub fn run<G, R>(&self, f: G) -> LockResult<R>
+    where
+        F: Sync,
+        G: FnOnce(&mut U) -> R + Send,
+        R: Send,
+    {
+        let projection = &self.projection;
+        self.lock.run(move |data| f(projection(data)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Lock;
+
+    extern crate std;
+
+    #[derive(Default)]
+    struct Pair {
+        left: usize,
+        right: usize,
+    }
+
+    #[test]
+    fn multi_thread_projection() {
+        let cnt = 100;
+        let lock = Lock::new(Pair::default());
+        let left = lock.project(|pair| &mut pair.left);
+        let right = lock.project(|pair| &mut pair.right);
+        std::thread::scope(|scope| {
+            for i in 0..cnt {
+                let left = &left;
+                let right = &right;
+                scope.spawn(move || {
+                    left.run(|data| *data += i).unwrap();
+                    right.run(|data| *data += 2 * i).unwrap();
+                });
+            }
+        });
+        assert_eq!(lock.run(|x| x.left).unwrap(), cnt * (cnt - 1) / 2);
+        assert_eq!(lock.run(|x| x.right).unwrap(), cnt * (cnt - 1));
+    }
+}
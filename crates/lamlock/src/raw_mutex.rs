@@ -0,0 +1,134 @@
+use core::{
+    ptr::NonNull,
+    sync::atomic::{AtomicPtr, Ordering},
+};
+
+use std::boxed::Box;
+
+use crate::{node::Node, rawlock::RawLock};
+
+/// A [`lock_api::RawMutex`] backed by the lamlock queue.
+///
+/// Locking takes the lock directly if it is free and nobody is queued. Otherwise, a holder node joins
+/// the queue like [`Lock::lock`](crate::Lock::lock) does, and the lock is handed over once a combiner reaches it.
+///
+/// Since `lock_api` guards run their critical sections on their own threads, nothing is ever combined.
+/// Used this way, the lock behaves as a plain queue lock and loses the throughput benefit of flat combining.
+/// Prefer [`Lock`](crate::Lock) where the code can be written as closures.
+///
+/// `lock_api` guards do not poison on panic, so neither does this mutex.
+///
+/// ```rust
+/// let mutex = lamlock::Mutex::new(0usize);
+/// std::thread::scope(|scope| {
+///     for _ in 0..4 {
+///         scope.spawn(|| *mutex.lock() += 1);
+///     }
+/// });
+/// assert_eq!(*mutex.lock(), 4);
+/// ```
+pub struct RawMutex {
+    raw: RawLock,
+    /// The holder node of the current owner if it had to queue, null if it took the lock on the fast path.
+    node: AtomicPtr<Node>,
+}
+
+/// A [`lock_api::Mutex`] using [`RawMutex`].
+pub type Mutex<T> = lock_api::Mutex<RawMutex, T>;
+
+/// A [`lock_api::MutexGuard`] for [`Mutex`].
+pub type MutexGuard<'a, T> = lock_api::MutexGuard<'a, RawMutex, T>;
+
+impl RawMutex {
+    #[inline(always)]
+    fn try_barge(&self) -> bool {
+        !self.raw.has_tail(Ordering::Relaxed) && self.raw.try_acquire().unwrap_or(false)
+    }
+}
+
+unsafe impl lock_api::RawMutex for RawMutex {
+    const INIT: Self = Self {
+        raw: RawLock::new(),
+        node: AtomicPtr::new(core::ptr::null_mut()),
+    };
+
+    type GuardMarker = lock_api::GuardNoSend;
+
+    fn lock(&self) {
+        self.raw.check_reentrancy();
+        if !self.try_barge() {
+            // The node must stay at a fixed address until the lock is released.
+            let node = NonNull::from(Box::leak(Box::new(Node::holder())));
+            Node::acquire(node, &self.raw).expect("RawMutex is never poisoned");
+            self.node.store(node.as_ptr(), Ordering::Relaxed);
+        }
+        self.raw.enter_critical();
+        self.raw.record_batch(1);
+    }
+
+    fn try_lock(&self) -> bool {
+        self.raw.check_reentrancy();
+        if !self.try_barge() {
+            return false;
+        }
+        self.raw.enter_critical();
+        self.raw.record_batch(1);
+        true
+    }
+
+    unsafe fn unlock(&self) {
+        self.raw.exit_critical();
+        let node = self.node.swap(core::ptr::null_mut(), Ordering::Relaxed);
+        match NonNull::new(node) {
+            None => self.raw.release(),
+            Some(node) => {
+                Node::release(node, &self.raw);
+                drop(unsafe { Box::from_raw(node.as_ptr()) });
+            }
+        }
+    }
+
+    fn is_locked(&self) -> bool {
+        self.raw.is_locked()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Mutex;
+
+    extern crate std;
+
+    #[test]
+    fn multi_thread_mutex() {
+        let cnt = 100;
+        let mutex = Mutex::new(0usize);
+        std::thread::scope(|scope| {
+            for i in 0..cnt {
+                let mutex = &mutex;
+                scope.spawn(move || {
+                    let mut guard = mutex.lock();
+                    let value = *guard;
+                    std::thread::yield_now();
+                    *guard = value + i;
+                });
+            }
+        });
+        assert_eq!(*mutex.lock(), cnt * (cnt - 1) / 2);
+    }
+
+    #[test]
+    fn try_lock_test() {
+        let mutex = Mutex::new(0usize);
+        let guard = mutex.lock();
+        assert!(mutex.is_locked());
+        assert!(std::thread::scope(|scope| scope
+            .spawn(|| mutex.try_lock().is_none())
+            .join()
+            .unwrap()));
+        drop(guard);
+        assert!(!mutex.is_locked());
+        *mutex.try_lock().unwrap() += 1;
+        assert_eq!(mutex.into_inner(), 1);
+    }
+}
@@ -1,35 +1,237 @@
+#[cfg(feature = "metrics")]
+use core::sync::atomic::AtomicU64;
+#[cfg(debug_assertions)]
+use core::sync::atomic::AtomicUsize;
 use core::{
     ptr::NonNull,
-    sync::atomic::{AtomicPtr, AtomicU32, Ordering},
+    sync::atomic::{AtomicPtr, AtomicU8, AtomicU32, Ordering, fence},
 };
 
-use crate::{LockNotPoisoned, LockPoisoned, LockResult, node::Node};
+use crate::{LockNotPoisoned, LockPoisoned, LockResult, PoisonKind, backoff::Backoff, node::Node};
 
+// The low bits of the status hold the state, the rest counts releases.
+// A reader that sees the same status before and after reading the data knows that no writer got in between.
 const UNLOCKED: u32 = 0;
 const LOCKED: u32 = 1;
 const POISONED: u32 = 2;
+const STATE_MASK: u32 = 0b11;
+const GENERATION: u32 = STATE_MASK + 1;
 
 pub struct RawLock {
     status: AtomicU32,
+    /// Why the lock was last poisoned, only meaningful while the status is `POISONED`.
+    poison_kind: AtomicU8,
     tail: AtomicPtr<Node>,
+    /// Maximum number of nodes a combiner runs before handing the lock to the next node.
+    combine_limit: u64,
+    /// Number of times the lock has been taken to run critical sections.
+    #[cfg(feature = "metrics")]
+    acquisitions: AtomicU64,
+    /// Number of critical sections run, counting every node drained by a combiner.
+    #[cfg(feature = "metrics")]
+    combined: AtomicU64,
+    /// Number of closures run directly by their caller without enqueueing.
+    #[cfg(feature = "metrics")]
+    fast_paths: AtomicU64,
+    /// Number of closures enqueued to be run by a combiner.
+    #[cfg(feature = "metrics")]
+    slow_paths: AtomicU64,
+    /// Thread currently executing critical sections, used to detect reentrancy.
+    #[cfg(debug_assertions)]
+    owner: AtomicUsize,
+}
+
+/// Identify the current thread, or return 0 if it cannot be told apart, which disables the check.
+/// The thread pointer is unique among live threads, and reading it needs neither `std` nor a system call.
+#[cfg(all(
+    debug_assertions,
+    not(miri),
+    target_os = "linux",
+    target_arch = "x86_64"
+))]
+fn current_thread() -> usize {
+    let thread: usize;
+    // The x86-64 TLS ABI stores the address of the thread control block at its start.
+    unsafe {
+        core::arch::asm!(
+            "mov {}, qword ptr fs:[0]",
+            out(reg) thread,
+            options(nostack, readonly, preserves_flags)
+        )
+    };
+    thread
+}
+
+/// Identify the current thread, or return 0 if it cannot be told apart, which disables the check.
+/// The thread pointer is unique among live threads, and reading it needs neither `std` nor a system call.
+#[cfg(all(
+    debug_assertions,
+    not(miri),
+    target_os = "linux",
+    target_arch = "aarch64"
+))]
+fn current_thread() -> usize {
+    let thread: usize;
+    unsafe {
+        core::arch::asm!(
+            "mrs {}, tpidr_el0",
+            out(reg) thread,
+            options(nomem, nostack, preserves_flags)
+        )
+    };
+    thread
+}
+
+/// Identify the current thread, or return 0 if it cannot be told apart, which disables the check.
+/// Without a known thread pointer, this falls back to the address of a thread-local, which needs `std`.
+#[cfg(all(
+    debug_assertions,
+    any(
+        miri,
+        not(all(
+            target_os = "linux",
+            any(target_arch = "x86_64", target_arch = "aarch64")
+        ))
+    )
+))]
+fn current_thread() -> usize {
+    #[cfg(any(feature = "std", test))]
+    {
+        extern crate std;
+        std::thread_local! {
+            static IDENTITY: u8 = const { 0 };
+        }
+        // It may be unavailable while the thread is torn down.
+        IDENTITY
+            .try_with(|identity| core::ptr::from_ref(identity).addr())
+            .unwrap_or(0)
+    }
+    #[cfg(not(any(feature = "std", test)))]
+    0
 }
 
 impl RawLock {
     pub const fn new() -> Self {
         Self {
             status: AtomicU32::new(0),
+            poison_kind: AtomicU8::new(PoisonKind::Panic as u8),
             tail: AtomicPtr::new(core::ptr::null_mut()),
+            combine_limit: u64::MAX,
+            #[cfg(feature = "metrics")]
+            acquisitions: AtomicU64::new(0),
+            #[cfg(feature = "metrics")]
+            combined: AtomicU64::new(0),
+            #[cfg(feature = "metrics")]
+            fast_paths: AtomicU64::new(0),
+            #[cfg(feature = "metrics")]
+            slow_paths: AtomicU64::new(0),
+            #[cfg(debug_assertions)]
+            owner: AtomicUsize::new(0),
+        }
+    }
+
+    pub const fn set_combine_limit(&mut self, limit: usize) {
+        self.combine_limit = limit as u64;
+    }
+
+    pub fn combine_limit(&self) -> u64 {
+        self.combine_limit
+    }
+
+    pub fn poison(&self, kind: PoisonKind) {
+        self.exit_critical();
+        let status = self.status.load(Ordering::Relaxed);
+        // A waiter that finds the lock already poisoned must not overwrite why it was poisoned.
+        if status & STATE_MASK == POISONED {
+            return;
+        }
+        // Published by the release store of the status below.
+        self.poison_kind.store(kind as u8, Ordering::Relaxed);
+        self.status
+            .store(status & !STATE_MASK | POISONED, Ordering::Release);
+    }
+
+    /// Mark the current thread as the one executing critical sections.
+    /// Only tracked under debug assertions.
+    #[inline(always)]
+    pub fn enter_critical(&self) {
+        #[cfg(debug_assertions)]
+        self.owner.store(current_thread(), Ordering::Relaxed);
+    }
+
+    /// Clear the mark set by [`RawLock::enter_critical`].
+    #[inline(always)]
+    pub fn exit_critical(&self) {
+        #[cfg(debug_assertions)]
+        self.owner.store(0, Ordering::Relaxed);
+    }
+
+    /// Panic if the current thread is executing a critical section of this lock.
+    /// Entering the lock again from there would deadlock.
+    #[inline(always)]
+    pub fn check_reentrancy(&self) {
+        #[cfg(debug_assertions)]
+        {
+            let current = current_thread();
+            debug_assert!(
+                current == 0 || self.owner.load(Ordering::Relaxed) != current,
+                "Lock is already in use by this thread, reentrancy detected"
+            );
+        }
+    }
+
+    /// Record that one acquisition ran `batch` critical sections.
+    /// Only tracked with the `metrics` feature.
+    #[inline(always)]
+    pub fn record_batch(&self, batch: u64) {
+        #[cfg(feature = "metrics")]
+        {
+            self.acquisitions.fetch_add(1, Ordering::Relaxed);
+            self.combined.fetch_add(batch, Ordering::Relaxed);
         }
+        #[cfg(not(feature = "metrics"))]
+        let _ = batch;
     }
 
-    pub fn poison(&self) {
-        self.status.store(POISONED, Ordering::Release);
+    /// Record that a closure took the fast path (`true`) or was enqueued (`false`).
+    /// Only tracked with the `metrics` feature.
+    #[inline(always)]
+    pub fn record_path(&self, fast: bool) {
+        #[cfg(feature = "metrics")]
+        if fast {
+            self.fast_paths.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.slow_paths.fetch_add(1, Ordering::Relaxed);
+        }
+        #[cfg(not(feature = "metrics"))]
+        let _ = fast;
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn path_stats(&self) -> (u64, u64) {
+        (
+            self.fast_paths.load(Ordering::Relaxed),
+            self.slow_paths.load(Ordering::Relaxed),
+        )
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn combine_stats(&self) -> crate::CombineStats {
+        crate::CombineStats {
+            acquisitions: self.acquisitions.load(Ordering::Relaxed),
+            combined: self.combined.load(Ordering::Relaxed),
+        }
     }
 
     pub fn has_tail(&self, ordering: Ordering) -> bool {
         !self.tail.load(ordering).is_null()
     }
 
+    #[cfg(test)]
+    pub fn tail(&self) -> *mut Node {
+        self.tail.load(Ordering::Acquire)
+    }
+
     pub fn swap_tail(&self, new_tail: NonNull<Node>) -> Option<NonNull<Node>> {
         let old_tail = self.tail.swap(new_tail.as_ptr(), Ordering::AcqRel);
         NonNull::new(old_tail)
@@ -45,58 +247,112 @@ impl RawLock {
             )
             .is_ok()
     }
+    /// Move the status from the `from` state to `LOCKED`, keeping the generation.
+    /// On failure, return the state observed instead.
+    fn lock_from(&self, from: u32) -> Result<(), u32> {
+        let mut current = self.status.load(Ordering::Relaxed);
+        loop {
+            if current & STATE_MASK != from {
+                return Err(current & STATE_MASK);
+            }
+            match self.status.compare_exchange_weak(
+                current,
+                current - from + LOCKED,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    // Order the writes to the data after the status change, for snapshot readers.
+                    fence(Ordering::Release);
+                    return Ok(());
+                }
+                Err(actual) => current = actual,
+            }
+        }
+    }
+    fn wait_while_locked(&self) {
+        let mut backoff = Backoff::new();
+        while self.status.load(Ordering::Relaxed) & STATE_MASK == LOCKED {
+            backoff.snooze();
+        }
+    }
     pub fn try_acquire(&self) -> LockResult<bool> {
-        match self
-            .status
-            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
-        {
-            Ok(_) => Ok(true),
+        match self.lock_from(UNLOCKED) {
+            Ok(()) => Ok(true),
             Err(LOCKED) => Ok(false),
             Err(_) => Err(LockPoisoned),
         }
     }
     pub fn acquire(&self) -> LockResult<()> {
         loop {
-            match self.status.compare_exchange(
-                UNLOCKED,
-                LOCKED,
-                Ordering::Acquire,
-                Ordering::Relaxed,
-            ) {
-                Ok(_) => return Ok(()),
-                Err(LOCKED) => {
-                    while self.status.load(Ordering::Relaxed) == LOCKED {
-                        core::hint::spin_loop();
-                    }
-                }
+            match self.lock_from(UNLOCKED) {
+                Ok(()) => return Ok(()),
+                Err(LOCKED) => self.wait_while_locked(),
                 Err(_) => return Err(LockPoisoned),
             }
         }
     }
     pub fn acquire_poison(&self) -> Result<(), LockNotPoisoned> {
         loop {
-            match self.status.compare_exchange(
-                POISONED,
-                LOCKED,
-                Ordering::Acquire,
-                Ordering::Relaxed,
-            ) {
-                Ok(_) => return Ok(()),
-                Err(LOCKED) => {
-                    while self.status.load(Ordering::Relaxed) == LOCKED {
-                        core::hint::spin_loop();
-                    }
-                }
+            match self.lock_from(POISONED) {
+                Ok(()) => return Ok(()),
+                Err(LOCKED) => self.wait_while_locked(),
                 Err(_) => return Err(LockNotPoisoned),
             }
         }
     }
+    /// Release the lock and start a new generation.
     pub fn release(&self) {
-        self.status.store(UNLOCKED, Ordering::Release);
+        let status = self.status.load(Ordering::Relaxed);
+        let next = (status & !STATE_MASK).wrapping_add(GENERATION) | UNLOCKED;
+        self.status.store(next, Ordering::Release);
+    }
+
+    /// Return why the lock is poisoned, or `None` if it is not.
+    pub fn poison_kind(&self) -> Option<PoisonKind> {
+        if self.status.load(Ordering::Acquire) & STATE_MASK != POISONED {
+            return None;
+        }
+        Some(self.last_poison_kind())
+    }
+
+    /// Poison the lock again after it was taken with [`RawLock::acquire_poison`], keeping the kind.
+    pub fn repoison(&self) {
+        self.poison(self.last_poison_kind());
+    }
+
+    fn last_poison_kind(&self) -> PoisonKind {
+        if self.poison_kind.load(Ordering::Relaxed) == PoisonKind::Explicit as u8 {
+            PoisonKind::Explicit
+        } else {
+            PoisonKind::Panic
+        }
+    }
+
+    /// Start an optimistic read of the data.
+    /// Return the status to validate the read against, or `None` if the lock is currently held.
+    pub fn read_begin(&self) -> LockResult<Option<u32>> {
+        let status = self.status.load(Ordering::Acquire);
+        match status & STATE_MASK {
+            UNLOCKED => Ok(Some(status)),
+            LOCKED => Ok(None),
+            _ => Err(LockPoisoned),
+        }
+    }
+
+    /// Check that no writer took the lock since [`RawLock::read_begin`] returned `status`.
+    pub fn read_validate(&self, status: u32) -> bool {
+        fence(Ordering::Acquire);
+        self.status.load(Ordering::Relaxed) == status
+    }
+
+    #[cfg(feature = "lock-api")]
+    pub fn is_locked(&self) -> bool {
+        self.status.load(Ordering::Relaxed) & STATE_MASK != UNLOCKED
     }
 
     #[cfg(test)]
     pub fn is_poisoned(&self, ordering: Ordering) -> bool {
-        self.status.load(ordering) == POISONED
+        self.status.load(ordering) & STATE_MASK == POISONED
     }
 }
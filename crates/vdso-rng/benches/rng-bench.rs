@@ -75,6 +75,32 @@ pub fn criterion_benchmark(c: &mut Criterion) {
         group.throughput(criterion::Throughput::Bytes(BYTES as u64));
         group.finish();
     }
+    {
+        const CHUNKS: usize = 1024;
+        const CHUNK_SIZE: usize = 8;
+        let mut array_of_chunks = vec![[0u8; CHUNK_SIZE]; CHUNKS];
+        let pool = Pool::new().expect("Failed to create pool");
+        let mut state = LocalState::new(&pool).expect("Failed to create local state");
+        let mut group = c.benchmark_group("small-chunks");
+        group.bench_function("fill-per-chunk-vgetrandom", |b| {
+            b.iter(|| {
+                for chunk in array_of_chunks.iter_mut() {
+                    state.fill(chunk, 0).unwrap();
+                }
+            });
+        });
+        group.bench_function("fill-many-vgetrandom", |b| {
+            b.iter(|| {
+                let mut bufs = array_of_chunks
+                    .iter_mut()
+                    .map(|chunk| &mut chunk[..])
+                    .collect::<Vec<_>>();
+                state.fill_many(&mut bufs, 0).unwrap();
+            });
+        });
+        group.throughput(criterion::Throughput::Bytes((CHUNKS * CHUNK_SIZE) as u64));
+        group.finish();
+    }
     {
         const TOTAL_CHUNKS: usize = 1024 * 1024 * 8;
         const CHUNK_SIZE: usize = 8;
@@ -0,0 +1,50 @@
+use linux_raw_sys::errno;
+use syscalls::{Sysno, raw_syscall};
+
+/// Fill `buf` with the `getrandom` system call, used when the pooled `vDSO` path is unavailable.
+/// A failing system call is reported as a custom [`getrandom::Error`] whose code is the errno.
+fn fill_syscall(mut buf: &mut [u8]) -> Result<(), getrandom::Error> {
+    while !buf.is_empty() {
+        let res =
+            unsafe { raw_syscall!(Sysno::getrandom, buf.as_mut_ptr(), buf.len(), 0) } as isize;
+        match res {
+            n if n >= 0 => buf = &mut core::mem::take(&mut buf)[n as usize..],
+            n if n == -(errno::EINTR as isize) => continue,
+            n => return Err(getrandom::Error::new_custom(-n as u16)),
+        }
+    }
+    Ok(())
+}
+
+/// The custom backend of the `getrandom` crate, see the `getrandom-backend` feature.
+#[unsafe(no_mangle)]
+unsafe extern "Rust" fn __getrandom_v03_custom(
+    dest: *mut u8,
+    len: usize,
+) -> Result<(), getrandom::Error> {
+    // The destination may be uninitialized.
+    let buf = unsafe {
+        core::ptr::write_bytes(dest, 0, len);
+        core::slice::from_raw_parts_mut(dest, len)
+    };
+    crate::thread_local_fill(buf, 0).or_else(|_| fill_syscall(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fill_syscall_test() {
+        let mut buf = [0u8; 64];
+        fill_syscall(&mut buf).unwrap();
+        assert!(buf.iter().any(|&x| x != 0), "Buffer should not be empty");
+    }
+
+    #[test]
+    fn custom_backend_test() {
+        let mut buf = [0u8; 64];
+        unsafe { __getrandom_v03_custom(buf.as_mut_ptr(), buf.len()) }.unwrap();
+        assert!(buf.iter().any(|&x| x != 0), "Buffer should not be empty");
+    }
+}
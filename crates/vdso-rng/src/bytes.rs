@@ -0,0 +1,74 @@
+use crate::{Error, LocalState};
+
+/// Default size of the internal buffer used by [`TryBytes`].
+pub const DEFAULT_BYTES_BUFFER: usize = 256;
+
+/// An iterator yielding random bytes from a [`LocalState`].
+///
+/// Bytes are generated in chunks of `N` bytes to amortize the cost of the `vDSO` call.
+/// Created by [`LocalState::try_bytes`] and [`LocalState::try_bytes_buffered`].
+pub struct TryBytes<'s, 'a, const N: usize = DEFAULT_BYTES_BUFFER> {
+    state: &'s mut LocalState<'a>,
+    buf: [u8; N],
+    pos: usize,
+}
+
+impl<'s, 'a, const N: usize> TryBytes<'s, 'a, N> {
+    pub(crate) fn new(state: &'s mut LocalState<'a>) -> Self {
+        Self {
+            state,
+            buf: [0; N],
+            pos: N,
+        }
+    }
+}
+
+impl<'s, 'a, const N: usize> Iterator for TryBytes<'s, 'a, N> {
+    type Item = Result<u8, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if N == 0 {
+            return None;
+        }
+        if self.pos == N {
+            if let Err(e) = self.state.fill(&mut self.buf, 0) {
+                return Some(Err(e));
+            }
+            self.pos = 0;
+        }
+        let byte = self.buf[self.pos];
+        self.pos += 1;
+        Some(Ok(byte))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use crate::{LocalState, Pool};
+
+    #[test]
+    fn collect_bytes() {
+        let pool = Pool::new().expect("Failed to create shared pool");
+        let mut local_state = LocalState::new(&pool).unwrap();
+        let bytes = local_state.bytes().take(10_000).collect::<Vec<_>>();
+        assert_eq!(bytes.len(), 10_000);
+        assert!(
+            bytes.iter().any(|&x| x != bytes[0]),
+            "Bytes should not be all identical"
+        );
+    }
+
+    #[test]
+    fn collect_bytes_buffered() {
+        let pool = Pool::new().expect("Failed to create shared pool");
+        let mut local_state = LocalState::new(&pool).unwrap();
+        let bytes = local_state
+            .try_bytes_buffered::<7>()
+            .take(100)
+            .collect::<Result<Vec<_>, _>>()
+            .expect("Failed to generate bytes");
+        assert_eq!(bytes.len(), 100);
+    }
+}
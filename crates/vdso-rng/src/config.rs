@@ -10,11 +10,27 @@ pub struct VGetrandomOpaqueParams {
     pub reserved: [c_uint; 13],
 }
 
+/// Memory layout of a [`crate::Pool`], see [`crate::Pool::config`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolInfo {
+    /// Size of a page in bytes.
+    pub page_size: usize,
+    /// Number of pages mapped each time the pool grows.
+    pub pages_per_block: usize,
+    /// Number of opaque states that fit into one page.
+    pub states_per_page: usize,
+    /// Size of one opaque state in bytes, as reported by the kernel.
+    pub size_of_opaque_states: usize,
+}
+
 #[derive(Debug)]
 pub struct Config {
     pub page_size: usize,
     pub pages_per_block: usize,
     pub states_per_page: usize,
+    /// Number of states carved out of one block, fixed whenever the block layout changes.
+    pub states_per_block: usize,
+    pub max_states: usize,
     pub function: VdsoFunc,
     pub params: VGetrandomOpaqueParams,
 }
@@ -41,10 +57,27 @@ impl Config {
             page_size,
             pages_per_block,
             states_per_page,
+            states_per_block: pages_per_block * states_per_page,
+            max_states: usize::MAX,
             function,
             params,
         }
     }
+
+    /// Change the number of pages per block and recompute the block layout.
+    pub fn set_pages_per_block(&mut self, pages_per_block: usize) {
+        self.pages_per_block = pages_per_block;
+        self.states_per_block = pages_per_block.saturating_mul(self.states_per_page);
+    }
+
+    pub fn info(&self) -> PoolInfo {
+        PoolInfo {
+            page_size: self.page_size,
+            pages_per_block: self.pages_per_block,
+            states_per_page: self.states_per_page,
+            size_of_opaque_states: self.params.size_of_opaque_states as usize,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -57,5 +90,12 @@ mod tests {
         assert!(config.page_size > 0);
         assert!(config.pages_per_block > 0);
         assert!(config.states_per_page > 0);
+        assert_eq!(
+            config.states_per_block,
+            config.pages_per_block * config.states_per_page
+        );
+        let info = config.info();
+        assert!(info.size_of_opaque_states > 0);
+        assert!(info.states_per_page * info.size_of_opaque_states <= info.page_size);
     }
 }
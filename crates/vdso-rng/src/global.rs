@@ -0,0 +1,84 @@
+use core::{cell::RefCell, ffi::c_uint};
+use std::sync::OnceLock;
+
+use crate::{Error, LocalState, Pool};
+
+/// Return the process-wide [`Pool`], creating it on first use.
+///
+/// The pool is created once with the default settings. If that fails (e.g. the platform does not
+/// provide the `vDSO` function), every call returns the same error, typically
+/// [`Error::NotSupported`] or [`Error::SymbolMissing`].
+///
+/// ```rust
+/// use vdso_rng::{global_pool, LocalState};
+///
+/// let pool = global_pool().unwrap();
+/// let mut local_state = LocalState::new(pool).unwrap();
+/// let mut buf = [0u8; 16];
+/// local_state.fill(&mut buf, 0).unwrap();
+/// assert!(std::ptr::eq(pool, global_pool().unwrap()));
+/// ```
+pub fn global_pool() -> Result<&'static Pool, Error> {
+    static GLOBAL_POOL: OnceLock<Result<Pool, Error>> = OnceLock::new();
+    GLOBAL_POOL.get_or_init(Pool::new).as_ref().map_err(|e| *e)
+}
+
+std::thread_local! {
+    static LOCAL_STATE: RefCell<Option<LocalState<'static>>> = const { RefCell::new(None) };
+}
+
+/// Fill the buffer with random bytes using a thread-local [`LocalState`] rented from [`global_pool`].
+///
+/// The pool is created on first use and the state is rented on the first call in each thread.
+/// When the thread exits, its state is returned to the pool, so threads that come and go do not
+/// make the pool grow. If the thread-local state is unavailable (e.g. during thread teardown),
+/// a temporary state is rented for this call only.
+///
+/// ```rust
+/// let mut buf = [0u8; 64];
+/// vdso_rng::thread_local_fill(&mut buf, 0).unwrap();
+/// assert!(buf.iter().any(|&x| x != 0));
+/// ```
+pub fn thread_local_fill(buf: &mut [u8], flag: c_uint) -> Result<(), Error> {
+    let pool = global_pool()?;
+    LOCAL_STATE
+        .try_with(|cell| {
+            let Ok(mut slot) = cell.try_borrow_mut() else {
+                return LocalState::new(pool)?.fill(buf, flag);
+            };
+            let state = match slot.as_mut() {
+                Some(state) => state,
+                None => slot.insert(LocalState::new(pool)?),
+            };
+            state.fill(buf, flag)
+        })
+        .unwrap_or_else(|_| LocalState::new(pool)?.fill(buf, flag))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thread_local_fill_recycles_on_exit() {
+        std::thread::scope(|scope| {
+            for _ in 0..16 {
+                scope.spawn(|| {
+                    let mut buf = [0u8; 64];
+                    thread_local_fill(&mut buf, 0).unwrap();
+                    assert!(buf.iter().any(|&x| x != 0), "Buffer should not be empty");
+                    // Thread-local destructors may run after the scope is joined, so drop the state
+                    // the same way the destructor would, and check that it went back to the pool.
+                    let state = LOCAL_STATE
+                        .with(|cell| cell.borrow_mut().take())
+                        .expect("State should be cached for the thread");
+                    #[cfg(debug_assertions)]
+                    let ptr = state.state;
+                    drop(state);
+                    #[cfg(debug_assertions)]
+                    assert!(!global_pool().unwrap().is_rented(ptr));
+                });
+            }
+        });
+    }
+}
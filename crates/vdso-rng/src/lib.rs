@@ -1,18 +1,39 @@
 #![no_std]
 #![doc = include_str!("../README.md")]
 extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+/// Emit a debug-level `tracing` event. Expands to nothing without the `tracing` feature.
+macro_rules! trace_event {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::debug!($($arg)*);
+    };
+}
 
 #[cfg(not(miri))]
 mod auxv;
+#[cfg(feature = "getrandom-backend")]
+mod backend;
+mod bytes;
 mod config;
+#[cfg(feature = "std")]
+mod global;
 mod pool;
 mod utils;
 #[cfg_attr(miri, path = "vdso_miri.rs")]
 mod vdso;
+pub use bytes::{DEFAULT_BYTES_BUFFER, TryBytes};
+pub use config::PoolInfo;
 use core::ffi::c_uint;
-use linux_raw_sys::errno;
-pub use pool::Pool;
+#[cfg(feature = "std")]
+pub use global::{global_pool, thread_local_fill};
+use linux_raw_sys::{errno, general::GRND_NONBLOCK};
 use pool::Ptr;
+pub use pool::{Pool, PoolBuilder};
+#[cfg(feature = "unstable-ffi")]
+pub use vdso::VdsoFunc;
 
 /// Errors that may occur during vdso getrandom operations.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -21,12 +42,20 @@ pub enum Error {
     /// This should not happen with safe usage.
     PoolPoisoned,
     /// The operation is not supported on this platform.
-    /// We failed to find the vdso symbol.
+    /// No `vDSO` is mapped into the process, or the architecture is not supported.
+    /// On aarch64, this is also reported if the `vDSO` is mapped as BTI-guarded pages,
+    /// but its `getrandom` function is not a valid indirect call target.
     NotSupported,
     /// Allocation failure occurred while trying to acquire a new random state.
     AllocationFailure,
     /// Normal errno as if it is returned from a system call.
     Errno(i32),
+    /// Random bytes are not available yet and the caller asked not to block.
+    /// See [`LocalState::try_fill_nonblock`].
+    WouldBlock,
+    /// The `vDSO` is mapped, but does not provide the `getrandom` function.
+    /// This is the case on kernels older than 6.11.
+    SymbolMissing,
 }
 
 impl core::fmt::Display for Error {
@@ -36,12 +65,28 @@ impl core::fmt::Display for Error {
             Error::AllocationFailure => write!(f, "Failed to allocate memory"),
             Error::Errno(e) => write!(f, "System call failed with error code: {e}"),
             Error::PoolPoisoned => write!(f, "Memory pool has been poisoned"),
+            Error::WouldBlock => write!(f, "Operation would block"),
+            Error::SymbolMissing => write!(f, "The vDSO does not provide getrandom"),
         }
     }
 }
 
 impl core::error::Error for Error {}
 
+#[cfg(feature = "std")]
+impl From<Error> for std::io::Error {
+    fn from(error: Error) -> Self {
+        use std::io::ErrorKind;
+        match error {
+            Error::Errno(e) => std::io::Error::from_raw_os_error(e),
+            Error::NotSupported | Error::SymbolMissing => ErrorKind::Unsupported.into(),
+            Error::AllocationFailure => ErrorKind::OutOfMemory.into(),
+            Error::WouldBlock => ErrorKind::WouldBlock.into(),
+            Error::PoolPoisoned => std::io::Error::other(error),
+        }
+    }
+}
+
 /// A local state for `vDSO`-based `getrandom` operations.
 ///
 /// This state is rented from a shared [`Pool`] and used to fill buffers with random bytes.
@@ -88,6 +133,48 @@ impl<'a> LocalState<'a> {
             inflight: false,
         })
     }
+    /// Pin the local state, to state in the type that it must not move while in use.
+    ///
+    /// The opaque state the kernel writes to lives in the pool's mapped memory and never moves, and
+    /// [`LocalState`] only holds a pointer to it, so it is [`Unpin`] and moving it between calls is fine.
+    /// A call cannot move it either, since it holds `&mut self` until it returns. Pinning is therefore
+    /// not required for soundness, but lets async code make the contract explicit. All methods remain
+    /// available on the pinned state, and the reentrancy check under debug builds works the same way.
+    /// ```rust
+    /// use vdso_rng::{LocalState, Pool};
+    ///
+    /// let pool = Pool::new().unwrap();
+    /// let mut local_state = LocalState::new(&pool).unwrap();
+    /// let mut pinned = local_state.pin();
+    /// let mut buf = [0u8; 16];
+    /// pinned.fill(&mut buf, 0).unwrap();
+    /// ```
+    pub fn pin(&mut self) -> core::pin::Pin<&mut Self> {
+        core::pin::Pin::new(self)
+    }
+
+    /// Discard the key material of the state, so that the kernel reinitializes it from its entropy pool.
+    ///
+    /// The `vDSO` ABI offers no explicit reseed operation. Instead, the kernel reinitializes a state whose
+    /// contents are all zeros, which is how states are wiped on `fork`. This method overwrites the state
+    /// the same way and then draws one byte, so that the state is reinitialized right away and any error
+    /// is reported here. Use it to recover from a suspected leak of the state, rather than relying on the
+    /// state being reinitialized when the kernel's generation changes.
+    /// ```rust
+    /// use vdso_rng::{LocalState, Pool};
+    ///
+    /// let pool = Pool::new().unwrap();
+    /// let mut local_state = LocalState::new(&pool).unwrap();
+    /// local_state.reseed().unwrap();
+    /// let mut buf = [0u8; 16];
+    /// local_state.fill(&mut buf, 0).unwrap();
+    /// ```
+    pub fn reseed(&mut self) -> Result<(), Error> {
+        let state_length = self.pool.config.params.size_of_opaque_states as usize;
+        unsafe { utils::zeroize(self.state.0, state_length) };
+        self.fill(&mut [0u8; 1], 0)
+    }
+
     /// Fill the provided buffer with random bytes. This method may not fill the entire buffer
     /// due to interrupts or low entropy conditions.
     pub fn try_fill(&mut self, buf: &mut [u8], flag: c_uint) -> Result<usize, Error> {
@@ -125,25 +212,169 @@ impl<'a> LocalState<'a> {
         }
     }
 
+    /// Same as [`LocalState::try_fill`], but never blocks waiting for the kernel's entropy pool to be initialized.
+    ///
+    /// `GRND_NONBLOCK` is added to `flag`. If no random bytes are available yet (e.g. early during boot),
+    /// [`Error::WouldBlock`] is returned instead of retrying, so the caller can degrade gracefully.
+    /// ```rust
+    /// use vdso_rng::{Error, LocalState, Pool};
+    ///
+    /// let pool = Pool::new().unwrap();
+    /// let mut local_state = LocalState::new(&pool).unwrap();
+    /// let mut buf = [0u8; 16];
+    /// match local_state.try_fill_nonblock(&mut buf, 0) {
+    ///     Ok(filled) => assert!(filled <= buf.len()),
+    ///     Err(Error::WouldBlock) => { /* fall back to something else */ }
+    ///     Err(e) => panic!("{e}"),
+    /// }
+    /// ```
+    pub fn try_fill_nonblock(&mut self, buf: &mut [u8], flag: c_uint) -> Result<usize, Error> {
+        match self.try_fill(buf, flag | GRND_NONBLOCK) {
+            Err(Error::Errno(e)) if e == errno::EAGAIN as i32 => Err(Error::WouldBlock),
+            result => result,
+        }
+    }
+
     /// Fill the provided buffer with random bytes. This method will block until the buffer is filled.
     /// It is implemented as a loop wrapping around [`LocalState::try_fill`].
-    pub fn fill(&mut self, mut buf: &mut [u8], flag: c_uint) -> Result<(), Error> {
-        while !buf.is_empty() {
-            match self.try_fill(buf, flag) {
+    pub fn fill(&mut self, buf: &mut [u8], flag: c_uint) -> Result<(), Error> {
+        self.fill_with_retries(buf, flag, usize::MAX)
+    }
+
+    /// Same as [`LocalState::fill`], but gives up after `max_retries` calls that failed with
+    /// `EAGAIN` or `EINTR`, returning that last error. Calls that make progress do not count as retries.
+    ///
+    /// This bounds the time spent in the loop if the calls keep getting interrupted, e.g. during a signal storm.
+    pub fn fill_with_retries(
+        &mut self,
+        buf: &mut [u8],
+        flag: c_uint,
+        max_retries: usize,
+    ) -> Result<(), Error> {
+        self.fill_inner(buf, flag, max_retries)
+            .map_err(|(_, error)| error)
+    }
+
+    /// Same as [`LocalState::fill`], but on error also reports how many bytes at the start of the
+    /// buffer have been filled. Only that prefix holds random bytes; the rest must be discarded.
+    /// ```rust
+    /// use vdso_rng::{LocalState, Pool};
+    ///
+    /// let pool = Pool::new().unwrap();
+    /// let mut local_state = LocalState::new(&pool).unwrap();
+    /// let mut buf = [0u8; 64];
+    /// if let Err((filled, _)) = local_state.fill_reporting(&mut buf, 0) {
+    ///     buf[filled..].fill(0);
+    /// }
+    /// ```
+    pub fn fill_reporting(&mut self, buf: &mut [u8], flag: c_uint) -> Result<(), (usize, Error)> {
+        self.fill_inner(buf, flag, usize::MAX)
+    }
+
+    /// Fill each of the buffers with random bytes, generating the bytes for small buffers in batches.
+    ///
+    /// Instead of one `vDSO` call per buffer, bytes are generated [`DEFAULT_BYTES_BUFFER`] at a time and
+    /// split between consecutive small buffers, which saves most of the per-call overhead when
+    /// drawing many tiny values. Buffers of at least that size are filled directly.
+    ///
+    /// This is a throughput optimization for independent draws: the buffers receive consecutive
+    /// parts of the same output, so there is no reseed boundary between them. Use separate calls to
+    /// [`LocalState::fill`] if that matters.
+    /// ```rust
+    /// use vdso_rng::{LocalState, Pool};
+    ///
+    /// let pool = Pool::new().unwrap();
+    /// let mut local_state = LocalState::new(&pool).unwrap();
+    /// let mut keys = [[0u8; 8]; 16];
+    /// let mut bufs = keys.iter_mut().map(|key| &mut key[..]).collect::<Vec<_>>();
+    /// local_state.fill_many(&mut bufs, 0).unwrap();
+    /// ```
+    pub fn fill_many(&mut self, bufs: &mut [&mut [u8]], flag: c_uint) -> Result<(), Error> {
+        let mut scratch = [0u8; DEFAULT_BYTES_BUFFER];
+        // Only generate as many bytes as the small buffers still need.
+        let mut remaining: usize = bufs
+            .iter()
+            .map(|buf| buf.len())
+            .filter(|&len| len < DEFAULT_BYTES_BUFFER)
+            .sum();
+        let mut available = 0;
+        let mut offset = 0;
+        for buf in bufs.iter_mut() {
+            if buf.len() >= DEFAULT_BYTES_BUFFER {
+                self.fill(buf, flag)?;
+                continue;
+            }
+            let mut buf = &mut **buf;
+            while !buf.is_empty() {
+                if offset == available {
+                    available = remaining.min(DEFAULT_BYTES_BUFFER);
+                    offset = 0;
+                    self.fill(&mut scratch[..available], flag)?;
+                }
+                let len = buf.len().min(available - offset);
+                buf[..len].copy_from_slice(&scratch[offset..offset + len]);
+                offset += len;
+                remaining -= len;
+                buf = &mut buf[len..];
+            }
+        }
+        Ok(())
+    }
+
+    fn fill_inner(
+        &mut self,
+        buf: &mut [u8],
+        flag: c_uint,
+        max_retries: usize,
+    ) -> Result<(), (usize, Error)> {
+        let mut written = 0;
+        let mut retries = 0;
+        while written < buf.len() {
+            match self.try_fill(&mut buf[written..], flag) {
                 Ok(filled) => {
-                    buf = &mut buf[filled..];
+                    written += filled;
                     continue;
                 }
                 Err(Error::Errno(e)) if e == errno::EAGAIN as i32 || e == errno::EINTR as i32 => {
+                    if retries >= max_retries {
+                        return Err((written, Error::Errno(e)));
+                    }
+                    retries += 1;
                     continue;
                 }
                 Err(e) => {
-                    return Err(e);
+                    return Err((written, e));
                 }
             }
         }
         Ok(())
     }
+
+    /// Return an iterator over random bytes. Bytes are generated in chunks of
+    /// [`DEFAULT_BYTES_BUFFER`] bytes via [`LocalState::fill`].
+    ///
+    /// The iterator ends if an error occurs. Use [`LocalState::try_bytes`] to observe the error.
+    /// ```rust
+    /// use vdso_rng::{Pool, LocalState};
+    ///
+    /// let pool = Pool::new().unwrap();
+    /// let mut local_state = LocalState::new(&pool).unwrap();
+    /// let token = local_state.bytes().take(16).collect::<Vec<u8>>();
+    /// assert_eq!(token.len(), 16);
+    /// ```
+    pub fn bytes(&mut self) -> impl Iterator<Item = u8> + '_ {
+        self.try_bytes().map_while(Result::ok)
+    }
+
+    /// Return an iterator over random bytes that yields the error if generation fails.
+    pub fn try_bytes(&mut self) -> TryBytes<'_, 'a> {
+        TryBytes::new(self)
+    }
+
+    /// Same as [`LocalState::try_bytes`], but with an internal buffer of `N` bytes.
+    pub fn try_bytes_buffered<const N: usize>(&mut self) -> TryBytes<'_, 'a, N> {
+        TryBytes::new(self)
+    }
 }
 
 impl<'a> Drop for LocalState<'a> {
@@ -177,6 +408,119 @@ mod tests {
         assert!(buf.iter().any(|&x| x != 0), "Buffer should not be empty");
     }
 
+    #[test]
+    fn fill_nonblock_local_state() {
+        let pool = Pool::new().expect("Failed to create shared pool");
+        let mut local_state = LocalState::new(&pool).unwrap();
+        let mut buf = [0u8; 64];
+        // The entropy pool is initialized long before tests run.
+        let filled = local_state.try_fill_nonblock(&mut buf, 0).unwrap();
+        assert!(filled > 0);
+        assert!(buf[..filled].iter().any(|&x| x != 0));
+    }
+
+    #[test]
+    fn fill_with_retries_local_state() {
+        static CALLS: core::sync::atomic::AtomicUsize = core::sync::atomic::AtomicUsize::new(0);
+        unsafe extern "C" fn always_interrupted(
+            _buf: *mut core::ffi::c_void,
+            _buf_len: usize,
+            _flags: c_uint,
+            _udata: *mut core::ffi::c_void,
+            _udata_len: usize,
+        ) -> core::ffi::c_int {
+            CALLS.fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+            -(errno::EINTR as core::ffi::c_int)
+        }
+        let mut pool = Pool::new().expect("Failed to create shared pool");
+        pool.config.function = always_interrupted;
+        let mut local_state = LocalState::new(&pool).unwrap();
+        let mut buf = [0u8; 64];
+        let res = local_state.fill_with_retries(&mut buf, 0, 3);
+        assert_eq!(res, Err(Error::Errno(errno::EINTR as i32)));
+        // The first attempt plus three retries.
+        assert_eq!(CALLS.load(core::sync::atomic::Ordering::Relaxed), 4);
+    }
+
+    #[test]
+    fn reseed_local_state() {
+        static SAW_WIPED: core::sync::atomic::AtomicBool =
+            core::sync::atomic::AtomicBool::new(false);
+        // Record whether the state arrives wiped, then key it like the kernel would.
+        unsafe extern "C" fn observe_state(
+            buf: *mut core::ffi::c_void,
+            buf_len: usize,
+            _flags: c_uint,
+            udata: *mut core::ffi::c_void,
+            udata_len: usize,
+        ) -> core::ffi::c_int {
+            let state = unsafe { core::slice::from_raw_parts_mut(udata as *mut u8, udata_len) };
+            SAW_WIPED.store(
+                state.iter().all(|&x| x == 0),
+                core::sync::atomic::Ordering::Relaxed,
+            );
+            state.fill(0xAA);
+            unsafe { core::ptr::write_bytes(buf as *mut u8, 0x55, buf_len) };
+            buf_len as core::ffi::c_int
+        }
+        let mut pool = Pool::new().expect("Failed to create shared pool");
+        pool.config.function = observe_state;
+        let mut local_state = LocalState::new(&pool).unwrap();
+        let mut buf = [0u8; 16];
+        local_state.fill(&mut buf, 0).unwrap();
+        local_state.fill(&mut buf, 0).unwrap();
+        assert!(!SAW_WIPED.load(core::sync::atomic::Ordering::Relaxed));
+        local_state.reseed().unwrap();
+        // The state was wiped before the kernel was asked to draw from it again.
+        assert!(SAW_WIPED.load(core::sync::atomic::Ordering::Relaxed));
+    }
+
+    #[test]
+    fn fill_reporting_local_state() {
+        static CALLS: core::sync::atomic::AtomicUsize = core::sync::atomic::AtomicUsize::new(0);
+        // Fill 16 bytes on the first call, then fail with a fatal error.
+        unsafe extern "C" fn fail_after_first_chunk(
+            buf: *mut core::ffi::c_void,
+            buf_len: usize,
+            _flags: c_uint,
+            _udata: *mut core::ffi::c_void,
+            _udata_len: usize,
+        ) -> core::ffi::c_int {
+            if CALLS.fetch_add(1, core::sync::atomic::Ordering::Relaxed) == 0 {
+                let len = buf_len.min(16);
+                unsafe { core::ptr::write_bytes(buf as *mut u8, 0xAA, len) };
+                return len as core::ffi::c_int;
+            }
+            -(errno::EFAULT as core::ffi::c_int)
+        }
+        let mut pool = Pool::new().expect("Failed to create shared pool");
+        pool.config.function = fail_after_first_chunk;
+        let mut local_state = LocalState::new(&pool).unwrap();
+        let mut buf = [0u8; 64];
+        let res = local_state.fill_reporting(&mut buf, 0);
+        assert_eq!(res, Err((16, Error::Errno(errno::EFAULT as i32))));
+        assert!(buf[..16].iter().all(|&x| x == 0xAA));
+    }
+
+    #[test]
+    fn fill_many_local_state() {
+        let pool = Pool::new().expect("Failed to create shared pool");
+        let mut local_state = LocalState::new(&pool).unwrap();
+        let mut small = [[0u8; 24]; 32];
+        let mut large = [0u8; 1024];
+        let mut empty = [0u8; 0];
+        let mut bufs = small.iter_mut().map(|buf| &mut buf[..]).collect::<Vec<_>>();
+        bufs.insert(7, &mut large);
+        bufs.insert(3, &mut empty);
+        local_state.fill_many(&mut bufs, 0).unwrap();
+        for buf in small.iter() {
+            assert!(buf.iter().any(|&x| x != 0), "Buffer should not be empty");
+        }
+        assert!(large.iter().any(|&x| x != 0), "Buffer should not be empty");
+        // Consecutive buffers get distinct bytes from the batch.
+        assert!(small.windows(2).all(|pair| pair[0] != pair[1]));
+    }
+
     #[test]
     fn multi_local_state() {
         let pool = Pool::new().expect("Failed to create shared pool");
@@ -242,4 +586,28 @@ mod tests {
             }
         });
     }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn io_error_conversion() {
+        use std::io::ErrorKind;
+        let error = std::io::Error::from(Error::Errno(errno::EINTR as i32));
+        assert_eq!(error.raw_os_error(), Some(errno::EINTR as i32));
+        assert_eq!(
+            std::io::Error::from(Error::NotSupported).kind(),
+            ErrorKind::Unsupported
+        );
+        assert_eq!(
+            std::io::Error::from(Error::AllocationFailure).kind(),
+            ErrorKind::OutOfMemory
+        );
+        assert_eq!(
+            std::io::Error::from(Error::PoolPoisoned).kind(),
+            ErrorKind::Other
+        );
+        assert_eq!(
+            std::io::Error::from(Error::WouldBlock).kind(),
+            ErrorKind::WouldBlock
+        );
+    }
 }
@@ -1,26 +1,33 @@
 use core::{ffi::c_void, ptr::NonNull};
 
-use alloc::vec::Vec;
+#[cfg(debug_assertions)]
+use alloc::collections::BTreeSet;
+use alloc::{sync::Arc, vec::Vec};
 use crossbeam_queue::SegQueue;
 use lamlock::Lock;
 
-use crate::{config::Config, utils};
+use crate::{
+    config::{Config, PoolInfo},
+    utils,
+};
 
 #[repr(transparent)]
 #[derive(Debug, Clone, Copy)]
 pub struct Ptr(pub(crate) NonNull<c_void>);
 
 unsafe impl Send for Ptr {}
+unsafe impl Sync for Ptr {}
 
 /// A shared state block pool for `vDSO`-based `getrandom` operations.
 ///
 /// This pool maintains a free list of opaque state blocks. Threads can rent a block
 /// from the pool and use it to generate random bytes. The pool is [`Sync`] and supports
-/// concurrent access. Under debug assertions, [`Pool::drop`] detects potential misuse,
-/// such as double drops.
+/// concurrent access. Under debug assertions, the pool detects potential misuse, such as
+/// a state being returned twice or [`Pool::drop`] finding states missing.
 ///
-/// The pool itself offers only [`Pool::new`]. To interact with the pool, see [`crate::LocalState`],
-/// which wraps individual state blocks for random generation.
+/// The pool itself offers only [`Pool::new`], [`Pool::builder`], [`Pool::from_region`], [`Pool::config`]
+/// and [`Pool::shrink_to_fit`].
+/// To interact with the pool, see [`crate::LocalState`], which wraps individual state blocks for random generation.
 ///
 /// ```rust
 /// use vdso_rng::Pool;
@@ -28,48 +35,267 @@ unsafe impl Send for Ptr {}
 /// ```
 ///
 /// ### Memory Behavior
-/// The pool grows monotonically with system parallelism. Opaque state blocks are stored
-/// in memory-mapped pages that are not backed by swap. When the system is under memory pressure,
+/// By default, each growth step maps enough pages to hold one state per available CPU,
+/// so the block size depends on the host. Use [`PoolBuilder`] to fix the layout explicitly.
+/// The pool grows with system parallelism and only shrinks when [`Pool::shrink_to_fit`] is called.
+/// Opaque state blocks are stored in memory-mapped pages that are not backed by swap. When the system is under memory pressure,
 /// the OS may reclaim these pages, which is generally safe.
 ///
 /// ## Safety
 /// - **Not async-signal-safe**: Using the pool in signal handlers may cause deadlocks.
 /// - **Fork safety**: After `fork`, the kernel wipes the random states to avoid leaks.
 ///   However, we do **not** guarantee correctness of pool usage across forks.
+/// - **Teardown**: The states hold key material. With the `zeroize` feature, they are overwritten
+///   before the memory is unmapped when the pool is dropped.
 pub struct Pool {
     pub(crate) config: Config,
     mmaps: Lock<Vec<Ptr>>,
-    freelist: SegQueue<Ptr>,
+    freelist: Arc<SegQueue<Ptr>>,
+    #[cfg(feature = "std")]
+    reclaimer: Option<Reclaimer>,
+    /// The caller-provided region the states live in, see [`Pool::from_region`].
+    region: Option<(Ptr, usize)>,
+    /// Addresses of the states currently rented out, used to detect double recycling.
+    #[cfg(debug_assertions)]
+    rented: Lock<BTreeSet<usize>>,
 }
 
-impl Pool {
-    pub fn new() -> Result<Self, crate::Error> {
-        let (function, page_size) =
-            crate::vdso::get_function_and_page_size().ok_or(crate::Error::NotSupported)?;
-        let config = unsafe { Config::new(function, page_size) };
+/// A background thread moving returned states back into the freelist.
+#[cfg(feature = "std")]
+struct Reclaimer {
+    sender: std::sync::mpsc::Sender<Ptr>,
+    thread: std::thread::JoinHandle<()>,
+}
+
+#[cfg(feature = "std")]
+impl Reclaimer {
+    fn spawn(freelist: Arc<SegQueue<Ptr>>) -> Result<Self, crate::Error> {
+        let (sender, receiver) = std::sync::mpsc::channel::<Ptr>();
+        let thread = std::thread::Builder::new()
+            .name("vdso-rng-reclaim".into())
+            .spawn(move || {
+                for ptr in receiver {
+                    freelist.push(ptr);
+                }
+            })
+            .map_err(|e| {
+                e.raw_os_error()
+                    .map_or(crate::Error::AllocationFailure, crate::Error::Errno)
+            })?;
+        Ok(Self { sender, thread })
+    }
+
+    /// Close the channel and wait until every pending state has been moved back into the freelist.
+    fn shutdown(self) {
+        drop(self.sender);
+        _ = self.thread.join();
+    }
+}
+
+/// A builder for [`Pool`] with explicit memory layout.
+///
+/// ```rust
+/// use vdso_rng::Pool;
+/// let _pool = Pool::builder()
+///     .pages_per_block(1)
+///     .max_states(64)
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PoolBuilder {
+    pages_per_block: Option<usize>,
+    max_states: Option<usize>,
+    #[cfg(feature = "std")]
+    async_recycle: bool,
+}
+
+impl PoolBuilder {
+    /// Create a builder with the default settings, equivalent to [`Pool::new`].
+    pub const fn new() -> Self {
+        Self {
+            pages_per_block: None,
+            max_states: None,
+            #[cfg(feature = "std")]
+            async_recycle: false,
+        }
+    }
+
+    /// Set the number of pages mapped each time the pool grows.
+    /// Without this setting, the block size is derived from the number of available CPUs.
+    ///
+    /// # Panics
+    /// Panics if `pages` is zero.
+    pub fn pages_per_block(mut self, pages: usize) -> Self {
+        assert!(pages > 0, "pages_per_block must be non-zero");
+        self.pages_per_block = Some(pages);
+        self
+    }
+
+    /// Limit the number of states the pool maps. Once the pool holds at least `states`
+    /// states, it stops growing and renting a new state fails with [`crate::Error::AllocationFailure`]
+    /// until one is returned. Since the pool grows by whole blocks, the limit is effectively rounded
+    /// up to the next block boundary.
+    pub fn max_states(mut self, states: usize) -> Self {
+        self.max_states = Some(states);
+        self
+    }
+
+    /// Return states to the pool on a background thread.
+    ///
+    /// When enabled, dropping a [`crate::LocalState`] only sends its state over a channel, and a dedicated
+    /// thread pushes it back into the freelist. This keeps the drop cheap on latency-sensitive paths, at the
+    /// cost of a returned state becoming available again slightly later. The thread is joined when the pool
+    /// is dropped.
+    #[cfg(feature = "std")]
+    pub fn async_recycle(mut self, enable: bool) -> Self {
+        self.async_recycle = enable;
+        self
+    }
+
+    /// Resolve the `vDSO` function and create the pool.
+    pub fn build(self) -> Result<Pool, crate::Error> {
+        let (function, page_size) = crate::vdso::get_function_and_page_size()?;
+        let mut config = unsafe { Config::new(function, page_size) };
+        if let Some(pages_per_block) = self.pages_per_block {
+            config.set_pages_per_block(pages_per_block);
+        }
+        if let Some(max_states) = self.max_states {
+            config.max_states = max_states;
+        }
         let mmaps = Lock::new(Vec::new());
-        let freelist = SegQueue::new();
-        Ok(Self {
+        let freelist = Arc::new(SegQueue::new());
+        #[cfg(feature = "std")]
+        let reclaimer = self
+            .async_recycle
+            .then(|| Reclaimer::spawn(freelist.clone()))
+            .transpose()?;
+        Ok(Pool {
             config,
             mmaps,
             freelist,
+            #[cfg(feature = "std")]
+            reclaimer,
+            region: None,
+            #[cfg(debug_assertions)]
+            rented: Lock::new(BTreeSet::new()),
+        })
+    }
+}
+
+impl Pool {
+    /// Create a pool with the default settings.
+    /// This is a shortcut for `Pool::builder().build()`.
+    pub fn new() -> Result<Self, crate::Error> {
+        Self::builder().build()
+    }
+
+    /// Create a [`PoolBuilder`] to configure the pool explicitly.
+    pub const fn builder() -> PoolBuilder {
+        PoolBuilder::new()
+    }
+
+    /// Create a pool whose states are laid out over a memory region provided by the caller,
+    /// for environments where mapping memory at runtime is not allowed.
+    ///
+    /// The pool never maps memory: once all states in the region are rented out, renting another one fails
+    /// with [`crate::Error::AllocationFailure`]. On drop, the region is left to the caller (it is still
+    /// wiped with the `zeroize` feature). [`Pool::shrink_to_fit`] has no effect on such a pool.
+    ///
+    /// Only whole pages of the region are used.
+    ///
+    /// # Safety
+    /// - `base` must be valid for reads and writes of `len` bytes, and the region must not be accessed
+    ///   otherwise until the pool is dropped.
+    /// - The region should be mapped with the protection and flags the kernel asks for, as [`Pool::new`]
+    ///   does. On current kernels, these include `MAP_DROPPABLE`, which makes the kernel wipe the states
+    ///   on `fork`. Other memory works as well, but a forked child may then produce the same bytes as its parent.
+    ///
+    /// # Panics
+    /// Panics if `base` is not aligned to the page size.
+    pub unsafe fn from_region(base: NonNull<c_void>, len: usize) -> Result<Self, crate::Error> {
+        let mut pool = Self::new()?;
+        let page_size = pool.config.page_size;
+        assert!(
+            base.addr().get().is_multiple_of(page_size),
+            "region must be aligned to the page size"
+        );
+        let pages = len / page_size;
+        // The capacity is fixed, so the pool must never grow.
+        pool.config.max_states = 0;
+        unsafe { Self::push_states(&pool.config, &pool.freelist, base, pages) };
+        pool.region = Some((Ptr(base), pages * page_size));
+        Ok(pool)
+    }
+
+    /// Number of states carved out of the caller-provided region, if any.
+    #[cfg(debug_assertions)]
+    fn region_states(&self) -> usize {
+        self.region.map_or(0, |(_, len)| {
+            len / self.config.page_size * self.config.states_per_page
         })
     }
+
+    /// Return the memory layout of the pool.
+    /// ```rust
+    /// use vdso_rng::Pool;
+    /// let pool = Pool::builder().pages_per_block(2).build().unwrap();
+    /// let info = pool.config();
+    /// assert_eq!(info.pages_per_block, 2);
+    /// assert!(info.states_per_page * info.size_of_opaque_states <= info.page_size);
+    /// ```
+    pub fn config(&self) -> PoolInfo {
+        self.config.info()
+    }
+
+    /// Map a new block and push its states into the freelist.
+    ///
+    /// This runs inside the `mmaps` lock, so it must not panic: a panic would poison the lock and
+    /// make every later [`Pool::get`] fail with [`crate::Error::PoolPoisoned`]. All failures are
+    /// reported as [`crate::Error::AllocationFailure`] instead, which leaves the pool usable.
     fn grow(
         mmaps: &mut Vec<Ptr>,
         config: &Config,
         freelist: &SegQueue<Ptr>,
     ) -> Result<(), crate::Error> {
-        let page = utils::mmap(
-            config.page_size * config.pages_per_block,
-            config.params.mmap_prot,
-            config.params.mmap_flags,
-        )
-        .ok_or(crate::Error::AllocationFailure)?;
+        let states = mmaps.len().saturating_mul(config.states_per_block);
+        if states >= config.max_states {
+            return Err(crate::Error::AllocationFailure);
+        }
+        let size = config
+            .page_size
+            .checked_mul(config.pages_per_block)
+            .ok_or(crate::Error::AllocationFailure)?;
+        // Reserve the slot before mapping, so that the mapping is never lost.
+        mmaps
+            .try_reserve(1)
+            .map_err(|_| crate::Error::AllocationFailure)?;
+        let Some(page) = utils::mmap(size, config.params.mmap_prot, config.params.mmap_flags)
+        else {
+            trace_event!(bytes = size, "failed to map a new state block");
+            return Err(crate::Error::AllocationFailure);
+        };
+        trace_event!(
+            bytes = size,
+            states = config.states_per_block,
+            blocks = mmaps.len() + 1,
+            "mapped a new state block"
+        );
         mmaps.push(Ptr(page));
+        unsafe { Self::push_states(config, freelist, page, config.pages_per_block) };
+        Ok(())
+    }
+
+    /// Carve `pages` pages starting at `base` into states and push them into the freelist.
+    unsafe fn push_states(
+        config: &Config,
+        freelist: &SegQueue<Ptr>,
+        base: NonNull<c_void>,
+        pages: usize,
+    ) {
         unsafe {
-            for p in 0..config.pages_per_block {
-                let page_ptr = page.byte_add(p * config.page_size);
+            for p in 0..pages {
+                let page_ptr = base.byte_add(p * config.page_size);
                 for s in 0..config.states_per_page {
                     let state_ptr =
                         page_ptr.byte_add(s * config.params.size_of_opaque_states as usize);
@@ -77,9 +303,26 @@ impl Pool {
                 }
             }
         }
-        Ok(())
     }
+    /// Rent a state, growing the pool if the freelist is empty.
+    ///
+    /// A failed growth is reported as [`crate::Error::AllocationFailure`] and leaves the pool usable,
+    /// so a later call succeeds once states are recycled or memory is available again. Only
+    /// [`Pool::grow`] is written not to panic; the growth is not wrapped in `catch_unwind`, so a panic
+    /// raised elsewhere while the `mmaps` lock is held (e.g. by an allocator hook) still poisons the lock,
+    /// after which this fails with [`crate::Error::PoolPoisoned`].
     pub(crate) fn get(&self) -> Result<Ptr, crate::Error> {
+        let ptr = self.get_untracked()?;
+        #[cfg(debug_assertions)]
+        {
+            let address = ptr.0.addr().get();
+            let fresh = self.rented.run(|rented| rented.insert(address));
+            debug_assert!(fresh.unwrap_or(true), "State {address:#x} handed out twice");
+        }
+        Ok(ptr)
+    }
+
+    fn get_untracked(&self) -> Result<Ptr, crate::Error> {
         if let Some(ptr) = self.freelist.pop() {
             return Ok(ptr);
         }
@@ -99,12 +342,131 @@ impl Pool {
             .unwrap_or(Err(crate::Error::PoolPoisoned))
     }
     pub(crate) fn recycle(&self, ptr: Ptr) {
+        // Unmark the state before it can be observed in the freelist again.
+        #[cfg(debug_assertions)]
+        {
+            let address = ptr.0.addr().get();
+            let rented = self.rented.run(|rented| rented.remove(&address));
+            debug_assert!(
+                rented.unwrap_or(true),
+                "State {address:#x} recycled twice, double free detected"
+            );
+        }
+        #[cfg(feature = "std")]
+        if let Some(reclaimer) = &self.reclaimer {
+            // The receiver only goes away once the pool is dropped, so this cannot fail in practice.
+            if let Err(err) = reclaimer.sender.send(ptr) {
+                self.freelist.push(err.0);
+            }
+            return;
+        }
         self.freelist.push(ptr);
     }
+
+    /// Return the resolved `vDSO` `getrandom` function, for callers managing opaque states on their own.
+    ///
+    /// This is an escape hatch: the pool does not track states used this way. See [`crate::VdsoFunc`] for
+    /// the contract of calling the function.
+    /// ```rust
+    /// use vdso_rng::Pool;
+    /// let pool = Pool::new().unwrap();
+    /// let mut params = [0u32; 16];
+    /// let function = pool.vdso_func();
+    /// unsafe { function(core::ptr::null_mut(), 0, 0, params.as_mut_ptr().cast(), !0) };
+    /// assert_eq!(params[0] as usize, pool.config().size_of_opaque_states);
+    /// ```
+    #[cfg(feature = "unstable-ffi")]
+    pub fn vdso_func(&self) -> crate::VdsoFunc {
+        self.config.function
+    }
+
+    /// Unmap the blocks whose states are all in the freelist, returning their memory to the OS.
+    ///
+    /// The pool never shrinks on its own. Long-running processes can call this after a burst of
+    /// activity to release the blocks mapped for it. States rented out, or still on their way back
+    /// with [`PoolBuilder::async_recycle`], keep their blocks alive. Renting a state afterwards maps
+    /// new blocks again as needed.
+    ///
+    /// This holds the pool lock while it scans the freelist, so renting states that need the pool
+    /// to grow waits until it finishes.
+    /// ```rust
+    /// use vdso_rng::{LocalState, Pool};
+    /// let pool = Pool::new().unwrap();
+    /// let states = (0..64).map(|_| LocalState::new(&pool).unwrap()).collect::<Vec<_>>();
+    /// drop(states);
+    /// pool.shrink_to_fit();
+    /// ```
+    pub fn shrink_to_fit(&self) {
+        if self.region.is_some() {
+            return;
+        }
+        _ = self.mmaps.run(|mmaps| {
+            let mut free = Vec::new();
+            while let Some(ptr) = self.freelist.pop() {
+                free.push(ptr);
+            }
+            // Count the free states of each block, looking up blocks by address.
+            mmaps.sort_unstable_by_key(|block| block.0.addr());
+            let block_of = |ptr: &Ptr| {
+                mmaps
+                    .partition_point(|block| block.0.addr() <= ptr.0.addr())
+                    .saturating_sub(1)
+            };
+            let mut counts = alloc::vec![0usize; mmaps.len()];
+            for ptr in &free {
+                counts[block_of(ptr)] += 1;
+            }
+            for ptr in free {
+                if counts[block_of(&ptr)] != self.config.states_per_block {
+                    self.freelist.push(ptr);
+                }
+            }
+            let mut index = 0;
+            mmaps.retain(|block| {
+                let unused = counts[index] == self.config.states_per_block;
+                index += 1;
+                if unused {
+                    unsafe { Self::unmap_block(&self.config, *block) };
+                }
+                !unused
+            });
+            trace_event!(blocks = mmaps.len(), "shrunk the pool");
+        });
+    }
+
+    /// Unmap a block mapped by [`Pool::grow`], wiping it first with the `zeroize` feature.
+    ///
+    /// # Safety
+    /// None of the states in the block may be used anymore.
+    unsafe fn unmap_block(config: &Config, block: Ptr) {
+        // Any mapped block passed the overflow check in `grow`.
+        let size = config.page_size.wrapping_mul(config.pages_per_block);
+        #[cfg(feature = "zeroize")]
+        unsafe {
+            utils::zeroize(block.0, size)
+        };
+        unsafe { utils::munmap(block.0, size) };
+    }
+
+    #[cfg(all(test, debug_assertions, feature = "std"))]
+    pub(crate) fn is_rented(&self, ptr: Ptr) -> bool {
+        let address = ptr.0.addr().get();
+        self.rented.run(|rented| rented.contains(&address)).unwrap()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn mapped_states(&self) -> usize {
+        let blocks = self.mmaps.run(|mmaps| mmaps.len()).unwrap();
+        blocks * self.config.states_per_block
+    }
 }
 
 impl Drop for Pool {
     fn drop(&mut self) {
+        #[cfg(feature = "std")]
+        if let Some(reclaimer) = self.reclaimer.take() {
+            reclaimer.shutdown();
+        }
         _ = self.mmaps.poison();
         #[cfg(debug_assertions)]
         let mut counter = 0;
@@ -118,13 +480,15 @@ impl Drop for Pool {
             #[cfg(debug_assertions)]
             debug_assert_eq!(
                 counter,
-                self.config.pages_per_block * self.config.states_per_page * mmaps.len(),
+                self.config.states_per_block * mmaps.len() + self.region_states(),
                 "Freelist should contain all states from all mmaps"
             );
             for ptr in mmaps.drain(..) {
-                unsafe {
-                    utils::munmap(ptr.0, self.config.page_size * self.config.pages_per_block)
-                };
+                unsafe { Self::unmap_block(&self.config, ptr) };
+            }
+            #[cfg(feature = "zeroize")]
+            if let Some((base, len)) = self.region {
+                unsafe { utils::zeroize(base.0, len) };
             }
             core::ops::ControlFlow::Continue(())
         });
@@ -143,6 +507,151 @@ mod tests {
         pool.recycle(ptr);
     }
 
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "double free detected")]
+    fn pool_double_recycle_test() {
+        let pool = Pool::new().expect("Failed to create pool");
+        let ptr = pool.get().expect("Failed to get pointer from pool");
+        pool.recycle(ptr);
+        pool.recycle(ptr);
+    }
+
+    #[test]
+    fn pool_builder_test() {
+        let pool = Pool::builder()
+            .pages_per_block(1)
+            .build()
+            .expect("Failed to create pool");
+        assert_eq!(pool.config.pages_per_block, 1);
+        let ptr = pool.get().expect("Failed to get pointer from pool");
+        pool.recycle(ptr);
+    }
+
+    #[test]
+    fn pool_max_states_test() {
+        let pool = Pool::builder()
+            .pages_per_block(1)
+            .max_states(1)
+            .build()
+            .expect("Failed to create pool");
+        let ptrs = (0..pool.config.states_per_page)
+            .map(|_| pool.get().expect("Failed to get pointer from pool"))
+            .collect::<Vec<_>>();
+        assert_eq!(pool.get().unwrap_err(), crate::Error::AllocationFailure);
+        for ptr in ptrs {
+            pool.recycle(ptr);
+        }
+        let ptr = pool.get().expect("Failed to get pointer from pool");
+        pool.recycle(ptr);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn pool_async_recycle_test() {
+        let pool = Pool::builder()
+            .async_recycle(true)
+            .build()
+            .expect("Failed to create pool");
+        std::thread::scope(|scope| {
+            for _ in 0..16 {
+                scope.spawn(|| {
+                    for _ in 0..16 {
+                        let mut state = crate::LocalState::new(&pool).unwrap();
+                        let mut buf = [0u8; 64];
+                        state.fill(&mut buf, 0).unwrap();
+                    }
+                });
+            }
+        });
+        // Dropping the pool joins the reclaim thread; the debug accounting in `Drop` then checks
+        // that every state made it back into the freelist.
+        drop(pool);
+    }
+
+    #[test]
+    fn pool_shrink_to_fit_test() {
+        let pool = Pool::builder()
+            .pages_per_block(1)
+            .build()
+            .expect("Failed to create pool");
+        let states_per_block = pool.config.states_per_block;
+        let mut ptrs = (0..states_per_block * 4)
+            .map(|_| pool.get().expect("Failed to get pointer from pool"))
+            .collect::<Vec<_>>();
+        assert_eq!(pool.mapped_states(), states_per_block * 4);
+        // Keep one state rented, so that its block stays mapped.
+        let kept = ptrs.pop().unwrap();
+        for ptr in ptrs {
+            pool.recycle(ptr);
+        }
+        pool.shrink_to_fit();
+        assert_eq!(pool.mapped_states(), states_per_block);
+        let ptrs = (0..states_per_block * 2)
+            .map(|_| pool.get().expect("Failed to get pointer from pool"))
+            .collect::<Vec<_>>();
+        assert_eq!(pool.mapped_states(), states_per_block * 3);
+        for ptr in ptrs {
+            pool.recycle(ptr);
+        }
+        pool.recycle(kept);
+        pool.shrink_to_fit();
+        assert_eq!(pool.mapped_states(), 0);
+    }
+
+    #[test]
+    fn pool_from_region_test() {
+        let page_size = Pool::new().expect("Failed to create pool").config.page_size;
+        // The region only needs to be page aligned, so any allocation works.
+        let layout = core::alloc::Layout::from_size_align(page_size * 2, page_size).unwrap();
+        let region = NonNull::new(unsafe { alloc::alloc::alloc_zeroed(layout) }).unwrap();
+        let pool = unsafe { Pool::from_region(region.cast(), layout.size()) }
+            .expect("Failed to create pool");
+        let ptrs = (0..pool.config.states_per_page * 2)
+            .map(|_| pool.get().expect("Failed to get pointer from pool"))
+            .collect::<Vec<_>>();
+        assert_eq!(pool.get().unwrap_err(), crate::Error::AllocationFailure);
+        assert_eq!(pool.mapped_states(), 0);
+        for ptr in ptrs {
+            pool.recycle(ptr);
+        }
+        pool.shrink_to_fit();
+        let mut state = crate::LocalState::new(&pool).unwrap();
+        let mut buf = [0u8; 64];
+        state.fill(&mut buf, 0).unwrap();
+        assert!(buf.iter().any(|&x| x != 0), "Buffer should not be empty");
+        drop(state);
+        drop(pool);
+        unsafe { alloc::alloc::dealloc(region.as_ptr(), layout) };
+    }
+
+    #[test]
+    fn pool_failed_grow_test() {
+        let mut pool = Pool::builder()
+            .pages_per_block(1)
+            .build()
+            .expect("Failed to create pool");
+        let ptrs = (0..pool.config.states_per_block)
+            .map(|_| pool.get().expect("Failed to get pointer from pool"))
+            .collect::<Vec<_>>();
+        // Make the next growth fail, as if memory ran out.
+        pool.config.max_states = pool.mapped_states();
+        assert_eq!(pool.get().unwrap_err(), crate::Error::AllocationFailure);
+        assert_eq!(pool.get().unwrap_err(), crate::Error::AllocationFailure);
+        // Once a state is recycled, it can be rented again.
+        pool.recycle(ptrs[0]);
+        let recycled = pool.get().expect("Failed to get pointer from pool");
+        // Once memory is available again, the pool grows as usual.
+        pool.config.max_states = usize::MAX;
+        let grown = pool.get().expect("Failed to get pointer from pool");
+        assert_eq!(pool.mapped_states(), pool.config.states_per_block * 2);
+        pool.recycle(grown);
+        pool.recycle(recycled);
+        for ptr in ptrs.into_iter().skip(1) {
+            pool.recycle(ptr);
+        }
+    }
+
     #[test]
     fn pool_multi_thread_test() {
         let parallelism = std::thread::available_parallelism().unwrap();
@@ -7,8 +7,20 @@ use core::{
 #[allow(unused_imports)]
 use syscalls::{Sysno, raw_syscall};
 
+use crate::vdso::VdsoFunc;
+
 #[cfg(not(miri))]
 pub fn guess_cpu_count() -> NonZero<usize> {
+    let count = affinity_cpu_count();
+    #[cfg(feature = "std")]
+    if let Some(quota) = cgroup_cpu_quota() {
+        return count.min(quota);
+    }
+    count
+}
+
+#[cfg(not(miri))]
+fn affinity_cpu_count() -> NonZero<usize> {
     let mut cpu_set = [0u8; 128];
     let res = unsafe {
         raw_syscall!(
@@ -31,15 +43,93 @@ pub fn guess_cpu_count() -> NonZero<usize> {
     .unwrap_or(one)
 }
 
+/// Number of CPUs granted by the CFS quota of the current cgroup, rounded up.
+/// Returns `None` if there is no quota or it cannot be read.
+#[cfg(all(feature = "std", not(miri)))]
+fn cgroup_cpu_quota() -> Option<NonZero<usize>> {
+    extern crate std;
+    use std::{format, fs::read_to_string, string::String};
+
+    let cgroups = read_to_string("/proc/self/cgroup").unwrap_or_default();
+    // cgroup v2: a single hierarchy, listed as `0::<path>`.
+    if let Some(path) = cgroups.lines().find_map(|line| line.strip_prefix("0::")) {
+        return cgroup_v2_quota("/sys/fs/cgroup", path);
+    }
+    // cgroup v1: quota and period live in separate files of the cpu controller.
+    let read = |name: &str| -> Option<String> {
+        read_to_string(format!("/sys/fs/cgroup/cpu/{name}"))
+            .or_else(|_| read_to_string(format!("/sys/fs/cgroup/cpu,cpuacct/{name}")))
+            .ok()
+    };
+    let quota = read("cpu.cfs_quota_us")?.trim().parse::<i64>().ok()?;
+    let period = read("cpu.cfs_period_us")?.trim().parse::<i64>().ok()?;
+    quota_to_cpus(quota, period)
+}
+
+/// Smallest quota among the cgroup at `path` below `root` and all of its ancestors,
+/// since a limit set on any of them applies to the whole subtree.
+#[cfg(all(feature = "std", not(miri)))]
+fn cgroup_v2_quota(root: &str, path: &str) -> Option<NonZero<usize>> {
+    extern crate std;
+    use std::{format, fs::read_to_string};
+
+    let mut path = path.trim_end_matches('/');
+    let mut quota = None;
+    loop {
+        let cpus = read_to_string(format!("{root}{path}/cpu.max"))
+            .ok()
+            .and_then(|content| parse_cpu_max(&content));
+        quota = match (quota, cpus) {
+            (Some(quota), Some(cpus)) => Some(cpus.min(quota)),
+            (quota, cpus) => quota.or(cpus),
+        };
+        match path.rfind('/') {
+            Some(parent) => path = &path[..parent],
+            None => return quota,
+        }
+    }
+}
+
+/// Parse the content of a cgroup v2 `cpu.max` file, i.e. `$MAX $PERIOD` where `$MAX` may be `max`.
+#[cfg(all(feature = "std", not(miri)))]
+fn parse_cpu_max(content: &str) -> Option<NonZero<usize>> {
+    let mut fields = content.split_whitespace();
+    let quota = fields.next()?.parse::<i64>().ok()?;
+    let period = fields.next().unwrap_or("100000").parse::<i64>().ok()?;
+    quota_to_cpus(quota, period)
+}
+
+#[cfg(all(feature = "std", not(miri)))]
+fn quota_to_cpus(quota: i64, period: i64) -> Option<NonZero<usize>> {
+    if quota <= 0 || period <= 0 {
+        return None;
+    }
+    NonZero::new((quota as u64).div_ceil(period as u64) as usize)
+}
+
 #[cfg(miri)]
 pub fn guess_cpu_count() -> NonZero<usize> {
     NonZero::new(4).unwrap()
 }
 
+/// Tell a missing `vDSO` apart from one that lacks the `getrandom` function.
+/// `func` is `None` if no `vDSO` is mapped, and `Some(None)` if the function was not found in it.
+pub fn classify(
+    func: Option<Option<VdsoFunc>>,
+    page_size: Option<usize>,
+) -> Result<(VdsoFunc, usize), crate::Error> {
+    match (func, page_size) {
+        (Some(Some(func)), Some(page_size)) => Ok((func, page_size)),
+        (Some(None), _) => Err(crate::Error::SymbolMissing),
+        _ => Err(crate::Error::NotSupported),
+    }
+}
+
 #[cfg(not(miri))]
 pub fn mmap(size: usize, mmap_prot: c_uint, mmap_flags: c_uint) -> Option<NonNull<c_void>> {
     let addr = unsafe { raw_syscall!(Sysno::mmap, 0, size, mmap_prot, mmap_flags, -1 as c_int, 0) };
-    if addr as isize == -1 {
+    // The raw system call reports failure as `-errno` rather than `MAP_FAILED`.
+    if (-4095..=-1).contains(&(addr as isize)) {
         return None;
     }
     NonNull::new(addr as *mut c_void)
@@ -48,8 +138,7 @@ pub fn mmap(size: usize, mmap_prot: c_uint, mmap_flags: c_uint) -> Option<NonNul
 #[cfg(miri)]
 pub fn mmap(size: usize, _mmap_prot: c_uint, _mmap_flags: c_uint) -> Option<NonNull<c_void>> {
     extern crate alloc;
-    let layout = core::alloc::Layout::from_size_align(size, crate::vdso::PAGE_SIZE)
-        .expect("Failed to create layout for mmap");
+    let layout = core::alloc::Layout::from_size_align(size, crate::vdso::PAGE_SIZE).ok()?;
     let ptr = unsafe { alloc::alloc::alloc_zeroed(layout) } as *mut c_void;
     NonNull::new(ptr)
 }
@@ -66,3 +155,121 @@ pub unsafe fn munmap(ptr: NonNull<c_void>, size: usize) {
         .expect("Failed to create layout for munmap");
     unsafe { alloc::alloc::dealloc(ptr.as_ptr() as *mut u8, layout) };
 }
+
+/// Overwrite the memory with zeros using volatile writes, so that the stores are not elided
+/// even if the memory is released right afterwards.
+pub unsafe fn zeroize(ptr: NonNull<c_void>, size: usize) {
+    let words = ptr.cast::<usize>();
+    for i in 0..size / core::mem::size_of::<usize>() {
+        unsafe { words.add(i).write_volatile(0) };
+    }
+    core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        unsafe extern "C" fn mocked(
+            _buf: *mut c_void,
+            _buf_len: usize,
+            _flags: c_uint,
+            _udata: *mut c_void,
+            _udata_len: usize,
+        ) -> c_int {
+            0
+        }
+        assert!(classify(Some(Some(mocked)), Some(4096)).is_ok());
+        assert_eq!(
+            classify(Some(None), Some(4096)).unwrap_err(),
+            crate::Error::SymbolMissing
+        );
+        assert_eq!(
+            classify(None, Some(4096)).unwrap_err(),
+            crate::Error::NotSupported
+        );
+        assert_eq!(
+            classify(Some(Some(mocked)), None).unwrap_err(),
+            crate::Error::NotSupported
+        );
+    }
+
+    #[cfg(not(miri))]
+    #[test]
+    fn test_mmap_failure() {
+        // A zero-sized mapping fails with `EINVAL`, which must not be mistaken for an address.
+        let ptr = mmap(
+            0,
+            linux_raw_sys::general::PROT_READ | linux_raw_sys::general::PROT_WRITE,
+            linux_raw_sys::general::MAP_PRIVATE | linux_raw_sys::general::MAP_ANONYMOUS,
+        );
+        assert!(ptr.is_none());
+    }
+
+    #[cfg(feature = "zeroize")]
+    #[test]
+    fn test_zeroize() {
+        let size = 4096;
+        let ptr = mmap(
+            size,
+            linux_raw_sys::general::PROT_READ | linux_raw_sys::general::PROT_WRITE,
+            linux_raw_sys::general::MAP_PRIVATE | linux_raw_sys::general::MAP_ANONYMOUS,
+        )
+        .unwrap();
+        unsafe {
+            core::ptr::write_bytes(ptr.as_ptr() as *mut u8, 0xAA, size);
+            zeroize(ptr, size);
+            let bytes = core::slice::from_raw_parts(ptr.as_ptr() as *const u8, size);
+            assert!(bytes.iter().all(|&b| b == 0));
+            munmap(ptr, size);
+        }
+    }
+
+    #[test]
+    fn test_guess_cpu_count() {
+        assert!(guess_cpu_count().get() >= 1);
+    }
+
+    #[cfg(all(feature = "std", not(miri)))]
+    #[test]
+    fn test_parse_cpu_max() {
+        assert_eq!(parse_cpu_max("max 100000\n"), None);
+        assert_eq!(parse_cpu_max("200000 100000\n").map(NonZero::get), Some(2));
+        assert_eq!(parse_cpu_max("150000 100000\n").map(NonZero::get), Some(2));
+        assert_eq!(parse_cpu_max("50000 100000\n").map(NonZero::get), Some(1));
+        assert_eq!(quota_to_cpus(-1, 100000), None);
+    }
+
+    #[cfg(all(feature = "std", not(miri)))]
+    #[test]
+    fn test_cgroup_v2_quota_ancestors() {
+        extern crate std;
+        use std::{fs, process};
+
+        let root = std::env::temp_dir().join(std::format!("vdso-rng-cgroup-{}", process::id()));
+        let leaf = root.join("a/b/c");
+        fs::create_dir_all(&leaf).unwrap();
+        fs::write(root.join("cpu.max"), "max 100000\n").unwrap();
+        fs::write(root.join("a/cpu.max"), "200000 100000\n").unwrap();
+        fs::write(root.join("a/b/cpu.max"), "max 100000\n").unwrap();
+        fs::write(leaf.join("cpu.max"), "400000 100000\n").unwrap();
+        let root_str = root.to_str().unwrap();
+        assert_eq!(
+            cgroup_v2_quota(root_str, "/a/b/c").map(NonZero::get),
+            Some(2)
+        );
+        assert_eq!(
+            cgroup_v2_quota(root_str, "/a/b/c/d").map(NonZero::get),
+            Some(2)
+        );
+        fs::write(root.join("a/cpu.max"), "max 100000\n").unwrap();
+        assert_eq!(
+            cgroup_v2_quota(root_str, "/a/b/c").map(NonZero::get),
+            Some(4)
+        );
+        assert_eq!(cgroup_v2_quota(root_str, "/").map(NonZero::get), None);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
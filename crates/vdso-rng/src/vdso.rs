@@ -3,12 +3,25 @@ use core::{
     ptr::NonNull,
 };
 
+use crate::Error;
 use linux_raw_sys::{
     ctypes::c_uint,
     elf::{Elf_Dyn, Elf_Ehdr, Elf_Phdr, Elf_Sym, Elf_Verdaux, Elf_Verdef, VER_FLG_BASE},
     elf_uapi::Elf64_Shdr,
 };
 
+/// Signature of the `vDSO` `getrandom` function:
+/// `(buffer, length, flags, opaque_state, opaque_length) -> bytes written or negated errno`.
+///
+/// # Safety
+/// Calling the function directly bypasses all the bookkeeping of [`crate::Pool`]. The caller must ensure that:
+/// - `buffer` is valid for writes of `length` bytes;
+/// - `opaque_state` points to a state of `size_of_opaque_states` bytes (see [`crate::PoolInfo`]), mapped with
+///   the protection and flags the kernel asks for, and `opaque_length` is that size;
+/// - a state is never used by two threads at the same time, and never copied or moved once in use.
+///
+/// Calling it with a null buffer, a zero length and an `opaque_length` of `!0` instead writes the kernel's
+/// `vgetrandom_opaque_params` (state size, `mmap` protection and flags) to `opaque_state`.
 pub type VdsoFunc = unsafe extern "C" fn(*mut c_void, usize, c_uint, *mut c_void, usize) -> c_int;
 
 #[repr(transparent)]
@@ -145,7 +158,15 @@ impl SymbolTable {
             let name = unsafe { CStr::from_ptr(self.strtab.as_ptr().add(name_offset)) };
             if name == target_name {
                 let version = self.find_version(i)?;
+                trace_event!(
+                    symbol = ?name,
+                    version = ?version,
+                    expected = ?target_version,
+                    "found vDSO symbol"
+                );
                 if version == target_version {
+                    // The symbol is a plain function. On aarch64 with BTI-guarded pages, the entry must also be
+                    // a valid indirect call target, which `get_function_and_page_size` checks.
                     let addr = unsafe { self.vdso_addr.byte_add(sym.st_value) };
                     return Some(unsafe {
                         core::mem::transmute::<NonNull<c_void>, VdsoFunc>(addr)
@@ -232,26 +253,147 @@ impl ElfShdrArray {
     }
 }
 
-pub fn get_function_and_page_size() -> Option<(VdsoFunc, usize)> {
-    let auxv = crate::auxv::Auxv::new()?;
-    let mut func = None;
+/// `HWCAP2_BTI` from `arch/arm64/include/uapi/asm/hwcap.h`.
+#[cfg(target_arch = "aarch64")]
+const HWCAP2_BTI: usize = 1 << 17;
+
+/// Whether `insn` may be the target of an indirect call on a BTI-guarded page.
+/// `bti c` and `bti jc` are explicit landing pads, `paciasp` and `pacibsp` implicit ones.
+#[cfg(any(target_arch = "aarch64", test))]
+const fn is_call_landing_pad(insn: u32) -> bool {
+    matches!(insn, 0xd503245f | 0xd50324df | 0xd503233f | 0xd503237f)
+}
+
+/// Whether the `NT_GNU_PROPERTY_TYPE_0` notes in `notes` mark the image as built with BTI landing pads.
+#[cfg(any(target_arch = "aarch64", test))]
+fn notes_have_bti(notes: &[u8]) -> bool {
+    use linux_raw_sys::elf_uapi::{
+        GNU_PROPERTY_AARCH64_FEATURE_1_AND, GNU_PROPERTY_AARCH64_FEATURE_1_BTI,
+        NT_GNU_PROPERTY_TYPE_0,
+    };
+    let word = |offset: usize| {
+        let bytes = notes.get(offset..offset.checked_add(4)?)?;
+        Some(u32::from_ne_bytes(bytes.try_into().ok()?))
+    };
+    // Notes and properties are padded to 8 bytes on 64-bit targets.
+    let mut offset = 0;
+    while let (Some(name_size), Some(desc_size), Some(kind)) =
+        (word(offset), word(offset + 4), word(offset + 8))
+    {
+        let name = offset + 12;
+        let desc = (name + name_size as usize).next_multiple_of(8);
+        let end = desc + desc_size as usize;
+        if kind == NT_GNU_PROPERTY_TYPE_0 && notes.get(name..desc.min(name + 4)) == Some(b"GNU\0") {
+            let mut property = desc;
+            while property + 8 <= end {
+                let (Some(kind), Some(size)) = (word(property), word(property + 4)) else {
+                    return false;
+                };
+                if kind == GNU_PROPERTY_AARCH64_FEATURE_1_AND {
+                    return word(property + 8).is_some_and(|features| {
+                        features & GNU_PROPERTY_AARCH64_FEATURE_1_BTI != 0
+                    });
+                }
+                property = (property + 8 + size as usize).next_multiple_of(8);
+            }
+        }
+        offset = end.next_multiple_of(8);
+    }
+    false
+}
+
+/// Whether the kernel maps the `vDSO` at `ehdr` as BTI-guarded pages.
+/// That is the case if the CPU supports BTI and the image was built with landing pads.
+#[cfg(target_arch = "aarch64")]
+unsafe fn is_guarded(ehdr: NonNull<Elf_Ehdr>, hwcap2: usize) -> bool {
+    if hwcap2 & HWCAP2_BTI == 0 {
+        return false;
+    }
+    let phoff = unsafe { ehdr.as_ref().e_phoff };
+    let phnum = unsafe { ehdr.as_ref().e_phnum } as usize;
+    let ptr = unsafe { ehdr.cast::<Elf_Phdr>().byte_add(phoff) };
+    let phdr_array = unsafe { core::slice::from_raw_parts(ptr.as_ptr(), phnum) };
+    phdr_array
+        .iter()
+        .filter(|ph| ph.p_type == linux_raw_sys::elf_uapi::PT_GNU_PROPERTY)
+        .any(|ph| {
+            let notes = unsafe {
+                core::slice::from_raw_parts(
+                    ehdr.cast::<u8>().byte_add(ph.p_offset).as_ptr(),
+                    ph.p_filesz as usize,
+                )
+            };
+            notes_have_bti(notes)
+        })
+}
+
+/// On guarded pages, calling through a function pointer is an indirect branch (`blr`), which faults
+/// unless it lands on a landing pad. The kernel builds its `vDSO` functions with them, but rather than
+/// crash on the first fill, report a function that does not start with one as [`Error::NotSupported`].
+#[cfg(target_arch = "aarch64")]
+unsafe fn check_branch_target(
+    func: VdsoFunc,
+    ehdr: NonNull<Elf_Ehdr>,
+    hwcap2: usize,
+) -> Result<(), Error> {
+    if !unsafe { is_guarded(ehdr, hwcap2) } {
+        return Ok(());
+    }
+    let insn = unsafe { (func as *const u32).read() };
+    trace_event!(insn, "checking BTI landing pad");
+    if !is_call_landing_pad(insn) {
+        trace_event!("vDSO getrandom is not a valid call target on BTI-guarded pages");
+        return Err(Error::NotSupported);
+    }
+    Ok(())
+}
+
+/// Load the `vDSO` image at `ehdr` and look up the `getrandom` function.
+unsafe fn load_function(ehdr: NonNull<Elf_Ehdr>) -> Option<VdsoFunc> {
+    unsafe {
+        let shdr = ElfShdrArray::load(ehdr)?;
+        let symbol_count = shdr.symbol_count();
+        let phdr_info = PhdrInfo::load(ehdr)?;
+        let symbol_table = SymbolTable::load(phdr_info, symbol_count)?;
+        symbol_table.find_symbol()
+    }
+}
+
+pub fn get_function_and_page_size() -> Result<(VdsoFunc, usize), Error> {
+    let auxv = crate::auxv::Auxv::new();
+    trace_event!(found = auxv.is_some(), "read the auxiliary vector");
+    let auxv = auxv.ok_or(Error::NotSupported)?;
+    let mut vdso = None;
     let mut page_size = None;
+    #[cfg(target_arch = "aarch64")]
+    let mut hwcap2 = 0;
     for entry in auxv.iter() {
         if entry.key == linux_raw_sys::general::AT_SYSINFO_EHDR.into() {
-            unsafe {
-                let ehdr = NonNull::new(entry.value as *mut Elf_Ehdr)?;
-                let shdr = ElfShdrArray::load(ehdr)?;
-                let symbol_count = shdr.symbol_count();
-                let phdr_info = PhdrInfo::load(ehdr)?;
-                let symbol_table = SymbolTable::load(phdr_info, symbol_count)?;
-                func = symbol_table.find_symbol();
-            }
+            trace_event!(address = entry.value, "found vDSO image");
+            vdso = NonNull::new(entry.value as *mut Elf_Ehdr);
         }
         if entry.key == linux_raw_sys::general::AT_PAGESZ.into() {
+            trace_event!(page_size = entry.value, "found page size");
             page_size = Some(entry.value as usize);
         }
+        #[cfg(target_arch = "aarch64")]
+        if entry.key == linux_raw_sys::general::AT_HWCAP2.into() {
+            hwcap2 = entry.value as usize;
+        }
+    }
+    let func = vdso.map(|ehdr| unsafe { load_function(ehdr) });
+    trace_event!(
+        vdso_found = func.is_some(),
+        symbol_found = matches!(func, Some(Some(_))),
+        page_size_found = page_size.is_some(),
+        "vDSO resolution finished"
+    );
+    let (func, page_size) = crate::utils::classify(func, page_size)?;
+    #[cfg(target_arch = "aarch64")]
+    if let Some(ehdr) = vdso {
+        unsafe { check_branch_target(func, ehdr, hwcap2)? };
     }
-    Some((func?, page_size?))
+    Ok((func, page_size))
 }
 
 #[cfg(test)]
@@ -267,4 +409,39 @@ mod tests {
             get_function_and_page_size().expect("Failed to get VDSO function and page size");
         assert!(page_size > 0, "Page size should be greater than 0");
     }
+
+    #[test]
+    fn test_notes_have_bti() {
+        let note = |features: u32| {
+            let words: [u32; 8] = [
+                4,
+                16,
+                5,
+                u32::from_ne_bytes(*b"GNU\0"),
+                0xc000_0000,
+                4,
+                features,
+                0,
+            ];
+            words.map(u32::to_ne_bytes).concat()
+        };
+        assert!(notes_have_bti(&note(1)));
+        assert!(notes_have_bti(&note(3)));
+        // PAC only.
+        assert!(!notes_have_bti(&note(2)));
+        assert!(!notes_have_bti(&note(1)[..20]));
+        assert!(!notes_have_bti(&[]));
+    }
+
+    #[test]
+    fn test_call_landing_pad() {
+        // bti c, bti jc, paciasp, pacibsp
+        for insn in [0xd503245f, 0xd50324df, 0xd503233f, 0xd503237f] {
+            assert!(is_call_landing_pad(insn));
+        }
+        // bti j, nop, stp x29, x30, [sp, #-16]!
+        for insn in [0xd503249f, 0xd503201f, 0xa9bf7bfd] {
+            assert!(!is_call_landing_pad(insn));
+        }
+    }
 }
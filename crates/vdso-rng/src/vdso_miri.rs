@@ -3,6 +3,7 @@ extern crate std;
 use crate::config::VGetrandomOpaqueParams;
 use core::ffi::{c_int, c_uint, c_void};
 use core::mem::MaybeUninit;
+/// Same as `VdsoFunc` in `vdso.rs`, which documents the signature and the safety contract.
 pub type VdsoFunc = unsafe extern "C" fn(*mut c_void, usize, c_uint, *mut c_void, usize) -> c_int;
 pub(crate) const PAGE_SIZE: usize = 8 * size_of::<usize>();
 
@@ -43,6 +44,6 @@ unsafe extern "C" fn mocked_vgetrandom(
     return written as c_int;
 }
 
-pub fn get_function_and_page_size() -> Option<(VdsoFunc, usize)> {
-    Some((mocked_vgetrandom, PAGE_SIZE))
+pub fn get_function_and_page_size() -> Result<(VdsoFunc, usize), crate::Error> {
+    crate::utils::classify(Some(Some(mocked_vgetrandom)), Some(PAGE_SIZE))
 }